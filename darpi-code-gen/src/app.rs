@@ -1,81 +1,508 @@
 //use crate::handler::{HAS_NO_PATH_ARGS_PREFIX, HAS_PATH_ARGS_PREFIX, NO_BODY_PREFIX};
+use crate::router::{check_mount, make_matcher, RouteDef};
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::ToTokens;
-use quote::{format_ident, quote};
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use quote::{format_ident, quote, quote_spanned};
+use std::collections::HashSet;
 use syn::parse::{Error as SynError, Parse, ParseStream, Result as SynResult};
 use syn::parse_quote::ParseQuote;
+use syn::spanned::Spanned;
 
 use syn::{
     braced, bracketed, punctuated::Punctuated, token, Error, Expr as SynExpr, Expr, ExprCall,
-    ExprLit, ExprPath, Ident, LitStr,
+    ExprLit, ExprPath, Ident, Lit, LitStr,
 };
 
 pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
-    let address_value = {
-        let av = match &config.address {
-            Address::Ident(id) => id.to_token_stream(),
-            Address::Lit(lit) => lit.to_token_stream(),
-        };
-        let q = quote! {&#av};
-        q.to_token_stream()
-    };
+    let mut addresses = config.address.iter().map(|address| match address {
+        Address::Expr(e) => quote! {&#e},
+        Address::Lit(lit) => quote! {&#lit},
+    });
+    let address_value = addresses.next().unwrap_or_default();
+    let listen: Vec<_> = addresses.map(|a| quote! {.listen(#a)}).collect();
 
     if config.handlers.is_empty() {
         return Err(Error::new(Span::call_site(), "no handlers registered"));
     }
 
-    let handler_len = config.handlers.len();
     let handlers = config.handlers;
 
+    let mounts = config.mounts;
+    let has_fallback = config.fallback.is_some();
+    let fallback_type = config.fallback.as_ref().map(ToTokens::to_token_stream);
+
+    // without a fallback handler, unknown routes are answered with an empty 404
+    // and skip the response middleware, unless there are mounted routes to try first, see `make_dispatch`
+    let fallback_call = match config.fallback {
+        Some(fallback) => quote! {
+            let catch = darpi::panic::Catch::from_parts(&parts);
+            let timer = darpi::timeout::Timer::from_parts(&parts);
+            let args = darpi::Args{
+                request_parts: &mut parts,
+                container: inner_module.clone(),
+                body: body,
+                route_args: std::collections::HashMap::new(),
+            };
+            catch.run(timer.call(&#fallback, args)).await
+        },
+        None => quote! {
+            Ok::<_, std::convert::Infallible>(darpi::Response::builder()
+                .status(darpi::StatusCode::NOT_FOUND)
+                .body(darpi::Body::empty())
+                .unwrap())
+        },
+    };
+
+    let mut mount_calls = vec![];
+    let mut mount_asserts = vec![];
+    let mut mount_exprs = vec![];
+    for (i, mount) in mounts.iter().enumerate() {
+        let index = syn::Index::from(i);
+        let prefix = &mount.prefix;
+        let expr = &mount.routes;
+        mount_exprs.push(quote! {#expr});
+        mount_asserts.push(quote_spanned! {expr.span()=>
+            assert_mount(&mounts.#index, &module);
+        });
+        mount_calls.push(quote! {
+            if let Some(path) = route.strip_prefix(#prefix).filter(|p| p.is_empty() || p.starts_with('/')) {
+                let path = if path.is_empty() { "/" } else { path };
+                match darpi::Routes::call(&inner_mounts.#index, path, &mut parts, inner_module.clone(), body).await {
+                    Ok(rb) => return rb,
+                    Err(b) => body = b,
+                }
+            }
+        });
+    }
+
+    let fallback = if mounts.is_empty() && !has_fallback {
+        None
+    } else {
+        Some(quote! {
+            async {
+                #(#mount_calls )*
+                #fallback_call
+            }.await
+        })
+    };
+
+    let (mounts_let, mounts_inner) = if mounts.is_empty() {
+        Default::default()
+    } else {
+        (
+            quote! {
+                fn assert_mount<C, R>(_: &R, _: &std::sync::Arc<C>)
+                where
+                    C: 'static + Sync + Send,
+                    R: darpi::Routes<C> + darpi::state::UsesState<AppStates>,
+                {
+                }
+
+                let mounts = std::sync::Arc::new((#(#mount_exprs ,)*));
+                #(#mount_asserts )*
+            },
+            quote! {let inner_mounts = std::sync::Arc::clone(&mounts);},
+        )
+    };
+
+    let middleware_types = config
+        .middleware
+        .as_ref()
+        .map(ReqResArray::types)
+        .unwrap_or_default();
+    let (middleware_req, middleware_res) = make_middleware(config.middleware, "", &[]);
+
     let HandlerTokens {
         routes,
+        handler_types: _,
+        middleware_bounds: _,
+        mut state_checks,
         route_arg_assert,
         route_arg_assert_def,
         routes_match,
-        is,
+        matcher,
         body_assert,
         body_assert_def,
-    } = make_handlers(handlers)?;
+        route_defs,
+    } = make_handlers(handlers, None, &middleware_res)?;
+
+    for mount in &mounts {
+        check_mount(&route_defs, &mount.prefix.value(), mount.prefix.span())?;
+    }
+
+    let route_possibilities = make_route_possibilities(&routes, &matcher);
+
+    if let (Some(Tls::Provider(provider)), None) = (&config.tls, &config.container) {
+        return Err(Error::new_spanned(
+            provider,
+            "a tls `provider` is resolved from the container, but there is no `container`",
+        ));
+    }
+
+    // the certificates are loaded by `bind`, which reports invalid ones like the other bind errors
+    let (tls_def, tls_param, tls_arg, tls_self, tls_acceptor) = match config.tls {
+        Some(Tls::Files { cert, key }) => (
+            quote! {tls: std::sync::Arc<darpi::tls::PemFiles>,},
+            quote! {, tls: darpi::tls::PemFiles},
+            quote! {, darpi::tls::PemFiles::new(#cert, #key)},
+            quote! {tls: std::sync::Arc::new(tls),},
+            quote! {darpi::tls::TlsAcceptor::new(std::sync::Arc::clone(&self.tls))},
+        ),
+        Some(Tls::Provider(provider)) => (
+            quote! {},
+            quote! {},
+            quote! {},
+            quote! {},
+            quote! {darpi::tls::TlsAcceptor::new(shaku::HasComponent::<#provider>::resolve(&*self.module))},
+        ),
+        None => Default::default(),
+    };
+    let (tls_let, incoming) = if tls_acceptor.is_empty() {
+        (quote! {}, quote! {listener})
+    } else {
+        (
+            quote! {
+                let tls = #tls_acceptor.map_err(|e| {
+                    std::io::Error::new(e.kind(), format!("could not load the tls certificates: {}", e))
+                })?;
+            },
+            quote! {tls.incoming(listener)},
+        )
+    };
+
+    let server = config.server.map_or(Default::default(), |server| {
+        let options = server.options.iter().map(|(k, v)| quote! {.#k(#v)});
+        quote! {
+            .server(darpi::server::ServerConfig::default()#(#options)*)
+        }
+    });
+
+    let timeout = config.timeout.map_or(Default::default(), |timeout| {
+        quote! {.timeout(#timeout)}
+    });
+
+    let cpu_pool = config.cpu_pool.map_or(Default::default(), |pool| {
+        let options = pool.options.iter().map(|(k, v)| quote! {.#k(#v)});
+        quote! {
+            .cpu_pool(
+                darpi::pool::CpuPool::builder()
+                    #(#options)*
+                    .build()
+                    .expect("could not build the cpu pool")
+            )
+        }
+    });
+
+    // without a container, the app has the unit container, which handlers and middleware
+    // without `#[inject]` accept as they are generic over it.
+    let (container_type, make_container) = config.container.map_or_else(
+        || (quote! {()}, quote! {()}),
+        |mp| {
+            let patj = mp.ttype;
+            let make_container_func = mp.factory;
+            (quote! {#patj}, quote! {#make_container_func})
+        },
+    );
+    let (module_def, module_param, module_self) = (
+        quote! {module: std::sync::Arc<#container_type>,},
+        quote! {module: std::sync::Arc<#container_type>,},
+        quote! {module: module,},
+    );
+
+    let state_types: Vec<&syn::Type> = config.state.iter().map(|(t, _)| t).collect();
+    let state_values: Vec<&Expr> = config.state.iter().map(|(_, v)| v).collect();
+    // everything registered has to use only the state of the app
+    state_checks.extend(fallback_type);
+    state_checks.extend(middleware_types);
+
+    let (jobs_req, jobs_res) = make_jobs(config.jobs);
+    let dispatch = make_dispatch(&routes_match, fallback, &middleware_res, &jobs_res);
+
+    let app = quote! {
+        #(#body_assert_def )*
+        #(#route_arg_assert_def )*
+
+        /// The types of the state of the app
+        #[allow(missing_docs)]
+        pub struct AppStates;
+        #(impl darpi::state::HasState<#state_types> for AppStates {})*
+
+         pub struct App {
+            #module_def
+            state: darpi::state::State,
+            #tls_def
+            addresses: Vec<darpi::listen::Address>,
+            shutdown_timeout: std::time::Duration,
+            panic_handler: std::sync::Arc<dyn darpi::panic::PanicHandler>,
+            cpu_pool: Option<darpi::pool::CpuPool>,
+            server: darpi::server::ServerConfig,
+            timeout: Option<darpi::timeout::Timeout>,
+        }
+
+        impl App {
+            pub fn new(address: &str, #module_param state: darpi::state::State #tls_param) -> Self {
+                #(#body_assert;)*
+                #(#route_arg_assert;)*
+                fn uses_state<U: darpi::state::UsesState<AppStates>>() {}
+                #(uses_state::<#state_checks>();)*
+                let address: darpi::listen::Address = address
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e));
+
+                Self {
+                    #module_self
+                    #tls_self
+                    state: state,
+                    addresses: vec![address],
+                    shutdown_timeout: darpi::shutdown::DEFAULT_TIMEOUT,
+                    panic_handler: std::sync::Arc::new(darpi::panic::DefaultPanicHandler),
+                    cpu_pool: None,
+                    server: darpi::server::ServerConfig::default(),
+                    timeout: None,
+                }
+            }
+
+            /// Listens on another address too, see `darpi::listen::Address` for the supported ones
+            pub fn listen(mut self, address: &str) -> Self {
+                let address: darpi::listen::Address = address
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e));
+                self.addresses.push(address);
+                self
+            }
+
+            /// How long in-flight requests and background jobs get to finish after the shutdown signal
+            pub fn shutdown_timeout(mut self, timeout: std::time::Duration) -> Self {
+                self.shutdown_timeout = timeout;
+                self
+            }
+
+            /// Reports panics of handlers and middleware and builds the response for them.
+            /// By default, they are logged and answered with `500 Internal Server Error`.
+            pub fn panic_handler<P>(mut self, handler: P) -> Self
+            where
+                P: darpi::panic::PanicHandler + 'static,
+            {
+                self.panic_handler = std::sync::Arc::new(handler);
+                self
+            }
+
+            /// The pool running the `CpuJob`s of this app, instead of the default one
+            pub fn cpu_pool(mut self, pool: darpi::pool::CpuPool) -> Self {
+                self.cpu_pool = Some(pool);
+                self
+            }
+
+            /// Keep-alive, timeouts and limits of the server
+            pub fn server(mut self, config: darpi::server::ServerConfig) -> Self {
+                self.server = config;
+                self
+            }
+
+            /// How long requests may take, before they are cancelled and answered with the `TimedOut` error.
+            /// Handlers with their own timeout are not subject to it.
+            pub fn timeout(mut self, timeout: impl Into<darpi::timeout::Timeout>) -> Self {
+                self.timeout = Some(timeout.into());
+                self
+            }
+
+            /// The request pipeline as a `hyper` service, which can be called without a listener
+            pub fn service(&self) -> darpi::server::AppService {
+                let module = self.module.clone();
+                let panic_handler = std::sync::Arc::clone(&self.panic_handler);
+                // the default pool is shared and only built once an app goes without its own
+                let cpu_pool = self
+                    .cpu_pool
+                    .clone()
+                    .unwrap_or_else(darpi::pool::CpuPool::default_pool);
+                let timeout = self.timeout;
+                let state = self.state.clone();
+                #mounts_let
+
+                darpi::server::AppService::new(move |mut r: darpi::Request<darpi::Body>| {
+                    use darpi::futures::FutureExt;
+                    use darpi::response::ResponderError;
+                    #[allow(unused_imports)]
+                    use darpi::RequestMiddleware;
+                    #[allow(unused_imports)]
+                    use darpi::ResponseMiddleware;
+                    use darpi::{RequestJobFactory, ResponseJobFactory};
+                    let inner_module = std::sync::Arc::clone(&module);
+                    #mounts_inner
+                    let catch = darpi::panic::Catch::new(std::sync::Arc::clone(&panic_handler), &mut r);
+                    darpi::timeout::Timer::start(timeout, &mut r);
+                    state.insert_into(&mut r);
+                    let scope = darpi::scope::Scope::new(&mut r);
 
-    let route_possibilities = quote! {
-        use std::convert::TryFrom;
+                    cpu_pool.clone().scope(catch.run(scope.run(async move {
+                        let route = r.uri().path().to_string();
+                        let method = r.method().clone();
+
+                        let (mut parts, mut body) = r.into_parts();
+
+                        #(#middleware_req )*
+                        #(#jobs_req )*
+
+                        let route_match = RoutePossibilities::get_route(&route, &method);
+                        #dispatch
+                    }))).boxed()
+                })
+            }
+
+            /// Runs the server until `SIGTERM` or `SIGINT`
+            pub async fn run(self) -> Result<(), darpi::server::Error> {
+                self.bind()?.await
+            }
+
+            /// Runs the server until `signal` resolves
+            pub async fn run_with_shutdown<F>(self, signal: F) -> Result<(), darpi::server::Error>
+            where
+                F: std::future::Future<Output = ()> + Send + 'static,
+            {
+                self.bind_with_shutdown(signal)?.await
+            }
+
+            /// Binds the listener, so the server can be run until `SIGTERM` or `SIGINT` by awaiting the result
+            pub fn bind(self) -> Result<darpi::server::Bound, darpi::server::Error> {
+                self.bind_with_shutdown(darpi::shutdown::signal())
+            }
+
+            /// Binds the listener, so the server can be run until `signal` resolves by awaiting the result
+            pub fn bind_with_shutdown<F>(self, signal: F) -> Result<darpi::server::Bound, darpi::server::Error>
+            where
+                F: std::future::Future<Output = ()> + Send + 'static,
+            {
+                #tls_let
+                let listener = darpi::server::Listener::bind(&self.addresses, &self.server)?;
+                let local_addrs = listener.local_addrs()?;
+                let incoming = #incoming;
+                let server = darpi::server::serve(
+                    incoming,
+                    self.service(),
+                    self.server.clone(),
+                    darpi::shutdown::Shutdown::new(self.shutdown_timeout),
+                    signal,
+                );
+                Ok(darpi::server::Bound::new(local_addrs, server))
+             }
+        }
+    };
+
+    let tokens = quote! {
+        {
+            #route_possibilities
+            #app
+            // built here, so they can use the variables of the caller, like a config
+            App::new(
+                #address_value,
+                std::sync::Arc::new(#make_container),
+                darpi::state::State::default()#(.with::<#state_types>(#state_values))*
+                #tls_arg
+            )#(#listen)*#cpu_pool#server#timeout
+        }
+    };
+    //panic!("{}", tokens.to_string());
+    Ok(tokens.into())
+}
+
+pub(crate) fn make_route_possibilities(
+    routes: &[proc_macro2::TokenStream],
+    matcher: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote! {
         #[allow(non_camel_case_types, missing_docs)]
         pub enum RoutePossibilities {
             #(#routes ,)*
         }
 
+        #[allow(missing_docs)]
+        pub enum RouteMatch<'a> {
+            Found(RoutePossibilities, std::collections::HashMap<&'a str, &'a str>),
+            Head(RoutePossibilities, std::collections::HashMap<&'a str, &'a str>),
+            Options(Vec<&'static str>),
+            MethodNotAllowed(Vec<&'static str>),
+            NotFound,
+        }
+
         impl RoutePossibilities {
-            pub fn get_route<'a>(&self, route: &'a str, method: &darpi::Method) -> Option<(darpi::ReqRoute<'a>, std::collections::HashMap<&'a str, &'a str>)> {
-                return match self {
-                    #(#is ,)*
-                };
+            pub fn get_route<'a>(route: &'a str, method: &darpi::Method) -> RouteMatch<'a> {
+                #matcher
             }
         }
-    };
-
-    let (module_def, module_let, module_self) = config.container.map_or(Default::default(), |mp| {
-        let patj = mp.ttype;
-        let make_container_func = mp.factory;
+    }
+}
 
-        (
-            quote! {module: std::sync::Arc<#patj>,},
-            quote! {let module = std::sync::Arc::new(#make_container_func);},
-            quote! {module: module,},
-        )
+/// Calls the handler for a `RouteMatch`, bound as `route_match`, and runs the response middleware and jobs.
+/// `fallback` answers `RouteMatch::NotFound` before the response middleware.
+/// Without it, the request is answered with an empty 404 right away.
+pub(crate) fn make_dispatch(
+    routes_match: &[proc_macro2::TokenStream],
+    fallback: Option<proc_macro2::TokenStream>,
+    middleware_res: &[proc_macro2::TokenStream],
+    jobs_res: &[proc_macro2::TokenStream],
+) -> proc_macro2::TokenStream {
+    let not_found = fallback.unwrap_or_else(|| {
+        quote! {
+            return Ok(darpi::Response::builder()
+                .status(darpi::StatusCode::NOT_FOUND)
+                .body(darpi::Body::empty())
+                .unwrap())
+        }
     });
 
+    quote! {
+                                // the answers to `OPTIONS` and unknown methods go through the response middleware too
+                                let allowed = |status, allowed: Vec<&'static str>| {
+                                    Ok::<_, std::convert::Infallible>(darpi::Response::builder()
+                                        .status(status)
+                                        .header(darpi::header::ALLOW, allowed.join(", "))
+                                        .body(darpi::Body::empty())
+                                        .unwrap())
+                                };
+
+                                let mut rb = match route_match {
+                                    RouteMatch::Found(variant, args) | RouteMatch::Head(variant, args) => {
+                                        let handler = (variant, args);
+                                        match handler.0 {
+                                            #(#routes_match ,)*
+                                        }
+                                    }
+                                    RouteMatch::Options(methods) => allowed(darpi::StatusCode::NO_CONTENT, methods),
+                                    RouteMatch::MethodNotAllowed(methods) => allowed(darpi::StatusCode::METHOD_NOT_ALLOWED, methods),
+                                    RouteMatch::NotFound => {
+                                        #not_found
+                                    }
+                                };
+
+                                if let Ok(mut rb) = rb.as_mut() {
+                                    #(#middleware_res )*
+                                    #(#jobs_res )*
+                                }
+
+                                rb
+    }
+}
+
+/// Builds the request and response middleware calls, in the order they have to run.
+/// `scope` prefixes the generated bindings, so the middleware of different groups can't clash.
+/// `outer` is the response middleware of the enclosing groups and the app, which answers a
+/// request middleware timeout together with the response middleware of this scope
+/// that doesn't need the values of the request middleware not run yet.
+pub(crate) fn make_middleware(
+    middleware: Option<ReqResArray>,
+    scope: &str,
+    outer: &[proc_macro2::TokenStream],
+) -> (Vec<proc_macro2::TokenStream>, Vec<proc_macro2::TokenStream>) {
     let (mut middleware_req, mut middleware_res) =
-        config.middleware.map_or(Default::default(), |middleware| {
+        middleware.map_or(Default::default(), |middleware| {
             let mut middleware_req = vec![];
             let mut middleware_res = vec![];
             let mut i = 0u16;
 
             middleware.request.map(|rm| {
                rm.iter().for_each(|e| {
-                    let m_arg_ident = format_ident!("m_arg_{}", i);
+                    let m_arg_ident = format_ident!("{}m_arg_{}", scope, i);
                     let mut sorter = 0_u16;
 
                     let (name, m_args) = match e {
@@ -85,7 +512,7 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
                                     if expr_call.func.to_token_stream().to_string() == "request" {
                                         let index: u16 = expr_call.args.first().unwrap().to_token_stream().to_string().parse().unwrap();
                                         sorter += index;
-                                        let i_ident = format_ident!("m_arg_{}", index);
+                                        let i_ident = format_ident!("{}m_arg_{}", scope, index);
                                         return quote!{#i_ident.clone()};
                                     }
                                 }
@@ -108,20 +535,18 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
                     };
 
 
-                    middleware_req.push((sorter, quote! {
-                    let #m_arg_ident = match #name::call(&mut parts, inner_module.clone(), &mut body, #m_args).await {
-                        Ok(k) => k,
-                        Err(e) => return Ok(e.respond_err()),
-                    };
-                }));
+                    middleware_req.push((sorter, i, m_arg_ident, quote! {
+                        #name::call(&mut parts, inner_module.clone(), &mut body, #m_args)
+                    }));
                     i += 1;
                 });
             });
 
             middleware.response.map(|ref mut rm| {
                 rm.iter_mut().for_each(|e| {
-                    let r_m_arg_ident = format_ident!("res_m_arg_{}", i);
+                    let r_m_arg_ident = format_ident!("{}res_m_arg_{}", scope, i);
                     let mut sorter = 0_u16;
+                    let mut uses = vec![];
 
                     let (name, m_args) = match e {
                         Func::Call(expr_call) => {
@@ -129,7 +554,8 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
                                 if let SynExpr::Call(expr_call) = arg {
                                     if expr_call.func.to_token_stream().to_string() == "request" {
                                         let index: u16 = expr_call.args.first().unwrap().to_token_stream().to_string().parse().unwrap();
-                                        let i_ident = format_ident!("m_arg_{}", index);
+                                        uses.push(index);
+                                        let i_ident = format_ident!("{}m_arg_{}", scope, index);
                                         return quote!{#i_ident.clone()};
                                     }
                                     if expr_call.func.to_token_stream().to_string() == "response" {
@@ -143,7 +569,8 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
                                         if let SynExpr::Call(expr_call) = tuple_arg {
                                             if expr_call.func.to_token_stream().to_string() == "request" {
                                                 let index: u16 = expr_call.args.first().unwrap().to_token_stream().to_string().parse().unwrap();
-                                                let i_ident = format_ident!("m_arg_{}", index);
+                                                uses.push(index);
+                                                let i_ident = format_ident!("{}m_arg_{}", scope, index);
                                                 return quote!{#i_ident.clone()};
                                             }
                                             if expr_call.func.to_token_stream().to_string() == "response" {
@@ -175,7 +602,7 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
                         }
                     };
 
-                    middleware_res.push((std::u16::MAX - i - sorter, quote! {
+                    middleware_res.push((std::u16::MAX - i - sorter, uses, quote! {
                     let #r_m_arg_ident = match #name::call(&mut rb, inner_module.clone(), #m_args).await {
                         Ok(k) => k,
                         Err(e) => return Ok(e.respond_err()),
@@ -191,7 +618,51 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
             )
         });
 
-    let (jobs_req, jobs_res) = config.jobs.map_or(Default::default(), |jobs| {
+    middleware_req.sort_by(|a, b| a.0.cmp(&b.0));
+    middleware_res.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut done = vec![];
+    let middleware_req: Vec<proc_macro2::TokenStream> = middleware_req
+        .into_iter()
+        .map(|(_, i, m_arg_ident, call)| {
+            // only the response middleware, whose request middleware values are bound already
+            let ready: Vec<&proc_macro2::TokenStream> = middleware_res
+                .iter()
+                .filter(|(_, uses, _)| uses.iter().all(|u| done.contains(u)))
+                .map(|(_, _, res)| res)
+                .collect();
+            done.push(i);
+            let timed_out = if ready.is_empty() && outer.is_empty() {
+                quote! {return Ok(timed_out.response())}
+            } else {
+                quote! {{
+                    let mut rb: Result<darpi::Response<darpi::Body>, std::convert::Infallible> = Ok(timed_out.response());
+                    if let Ok(mut rb) = rb.as_mut() {
+                        #(#ready )*
+                        #(#outer )*
+                    }
+                    return rb;
+                }}
+            };
+            quote! {
+                let #m_arg_ident = match darpi::timeout::Timer::from_parts(&parts).within(#call).await {
+                    Ok(Ok(k)) => k,
+                    Ok(Err(e)) => return Ok(e.respond_err()),
+                    Err(timed_out) => #timed_out,
+                };
+            }
+        })
+        .collect();
+    let middleware_res: Vec<proc_macro2::TokenStream> =
+        middleware_res.into_iter().map(|e| e.2).collect();
+
+    (middleware_req, middleware_res)
+}
+
+pub(crate) fn make_jobs(
+    jobs: Option<ReqResArray>,
+) -> (Vec<proc_macro2::TokenStream>, Vec<proc_macro2::TokenStream>) {
+    let (jobs_req, jobs_res) = jobs.map_or(Default::default(), |jobs| {
         let mut jobs_req = vec![];
         let mut jobs_res = vec![];
 
@@ -299,171 +770,179 @@ pub(crate) fn make_app(config: Config) -> Result<TokenStream, SynError> {
         (jobs_req, jobs_res)
     });
 
-    middleware_req.sort_by(|a, b| a.0.cmp(&b.0));
-    middleware_res.sort_by(|a, b| a.0.cmp(&b.0));
+    (jobs_req, jobs_res)
+}
 
-    let middleware_req: Vec<proc_macro2::TokenStream> =
-        middleware_req.into_iter().map(|e| e.1).collect();
-    let middleware_res: Vec<proc_macro2::TokenStream> =
-        middleware_res.into_iter().map(|e| e.1).collect();
+pub(crate) struct HandlerTokens {
+    pub routes: Vec<proc_macro2::TokenStream>,
+    pub handler_types: Vec<proc_macro2::TokenStream>,
+    /// The handlers and the middleware of their groups
+    pub state_checks: Vec<proc_macro2::TokenStream>,
+    /// See `Scope::middleware_bounds`
+    pub middleware_bounds: Vec<proc_macro2::TokenStream>,
+    pub route_arg_assert: Vec<proc_macro2::TokenStream>,
+    pub route_arg_assert_def: Vec<proc_macro2::TokenStream>,
+    pub routes_match: Vec<proc_macro2::TokenStream>,
+    pub matcher: proc_macro2::TokenStream,
+    pub body_assert: Vec<proc_macro2::TokenStream>,
+    pub body_assert_def: Vec<proc_macro2::TokenStream>,
+    /// Every route, to check the mount prefixes against
+    pub route_defs: Vec<RouteDef>,
+}
 
-    let app = quote! {
-        #(#body_assert_def )*
-        #(#route_arg_assert_def )*
+/// A handler with its group prefixes already applied
+struct ScopedHandler {
+    handler: Handler,
+    route: String,
+    span: Span,
+    scopes: Vec<usize>,
+}
 
-         pub struct App {
-            #module_def
-            handlers: std::sync::Arc<[RoutePossibilities; #handler_len]>,
-            address: std::net::SocketAddr,
-        }
+/// The middleware and jobs of a `group`, run only for the handlers inside of it
+pub(crate) struct Scope {
+    pub middleware_req: Vec<proc_macro2::TokenStream>,
+    pub middleware_res: Vec<proc_macro2::TokenStream>,
+    pub jobs_req: Vec<proc_macro2::TokenStream>,
+    pub jobs_res: Vec<proc_macro2::TokenStream>,
+    pub middleware_types: Vec<proc_macro2::TokenStream>,
+    /// The middleware bounds for the container `C`, of the routes generic over it
+    pub middleware_bounds: Vec<proc_macro2::TokenStream>,
+}
 
-        impl App {
-            pub fn new(address: &str) -> Self {
-                #(#body_assert;)*
-                #(#route_arg_assert;)*
-                let address: std::net::SocketAddr = address
-                    .parse()
-                    .expect(&format!("invalid server address: `{}`", address));
+fn flatten(
+    entries: Punctuated<Entry, token::Comma>,
+    prefix: &str,
+    parents: &[usize],
+    outer: &[proc_macro2::TokenStream],
+    scopes: &mut Vec<Scope>,
+    handlers: &mut Vec<ScopedHandler>,
+) -> Result<(), SynError> {
+    for entry in entries {
+        match entry {
+            Entry::Handler(handler) => {
+                let route = match &handler.route.lit {
+                    Lit::Str(s) => s.value(),
+                    _ => {
+                        return Err(Error::new_spanned(
+                            &handler.route,
+                            "route must be a string literal",
+                        ))
+                    }
+                };
+                let route = if prefix.is_empty() {
+                    route
+                } else if route == "/" {
+                    prefix.to_string()
+                } else {
+                    format!("{}{}", prefix, route)
+                };
 
-                #module_let
-                Self {
-                    #module_self
-                    handlers: std::sync::Arc::new([#(RoutePossibilities::#routes ,)*]),
-                    address: address,
-                }
+                handlers.push(ScopedHandler {
+                    span: handler.route.span(),
+                    handler,
+                    route,
+                    scopes: parents.to_vec(),
+                });
             }
+            Entry::Group(group) => {
+                let group_prefix = group.prefix.value();
+                if !group_prefix.starts_with('/') || group_prefix.ends_with('/') {
+                    return Err(Error::new_spanned(
+                        group.prefix,
+                        "group prefix must start with `/` and must not end with it",
+                    ));
+                }
 
-             pub async fn run(self) -> Result<(), darpi::Error> {
-                let address = self.address;
-                let module = self.module.clone();
-                let handlers = self.handlers.clone();
-
-                std::panic::set_hook(Box::new(|panic| {
-                    darpi::log::warn!("panic reason:  `{}`", panic);
-                }));
-
-                darpi::rayon::ThreadPoolBuilder::new()
-                    .panic_handler(|panic| {
-                        let msg = match panic.downcast_ref::<&'static str>() {
-                            Some(s) => *s,
-                            None => match panic.downcast_ref::<String>() {
-                                Some(s) => &s[..],
-                                None => "Unknown",
-                            },
-                        };
-                        darpi::log::warn!("panic reason:  `{}`", msg);
-                    })
-                    .build_global().unwrap();
-
-                let make_svc = darpi::service::make_service_fn(move |_conn| {
-                    let inner_module = std::sync::Arc::clone(&module);
-                    let inner_handlers = std::sync::Arc::clone(&handlers);
-
-                    async move {
-                        Ok::<_, std::convert::Infallible>(darpi::service::service_fn(move |r: darpi::Request<darpi::Body>| {
-                            use darpi::futures::FutureExt;
-                            use darpi::response::ResponderError;
-                            #[allow(unused_imports)]
-                            use darpi::RequestMiddleware;
-                            #[allow(unused_imports)]
-                            use darpi::ResponseMiddleware;
-                            use darpi::{RequestJobFactory, ResponseJobFactory};
-                            use darpi::Handler;
-                            let inner_module = std::sync::Arc::clone(&inner_module);
-                            let inner_handlers = std::sync::Arc::clone(&inner_handlers);
-
-                            async move {
-                                let route = r.uri().path().to_string();
-                                let method = r.method().clone();
-
-                                let (mut parts, mut body) = r.into_parts();
-
-                                #(#middleware_req )*
-                                #(#jobs_req )*
-
-                                let mut handler = None;
-                                for rp in inner_handlers.iter() {
-                                    if let Some(rr) = rp.get_route(&route, &method) {
-                                        handler = Some((rp, rr));
-                                        break;
-                                    }
-                                }
-
-                                let handler = match handler {
-                                    Some(s) => s,
-                                    None => return  async {
-                                         Ok::<_, std::convert::Infallible>(darpi::Response::builder()
-                                                .status(darpi::StatusCode::NOT_FOUND)
-                                                .body(darpi::Body::empty())
-                                                .unwrap())
-                                    }.await,
-                                };
-
-                                let mut rb = match handler.0 {
-                                    #(#routes_match ,)*
-                                };
-
-                                if let Ok(mut rb) = rb.as_mut() {
-                                    #(#middleware_res )*
-                                    #(#jobs_res )*
-                                }
-
-                                rb
-                            }
-                        }))
-                    }
+                let scope = format!("g{}_", scopes.len());
+                let middleware_types = group
+                    .middleware
+                    .as_ref()
+                    .map(ReqResArray::types)
+                    .unwrap_or_default();
+                let middleware_bounds = group
+                    .middleware
+                    .as_ref()
+                    .map(|m| m.bounds(&quote! {C}))
+                    .unwrap_or_default();
+                // a timeout of the group's request middleware is answered by the enclosing groups too
+                let group_outer: Vec<proc_macro2::TokenStream> = parents
+                    .iter()
+                    .rev()
+                    .flat_map(|i| scopes[*i].middleware_res.iter().cloned())
+                    .chain(outer.iter().cloned())
+                    .collect();
+                let (middleware_req, middleware_res) =
+                    make_middleware(group.middleware, &scope, &group_outer);
+                let (jobs_req, jobs_res) = make_jobs(group.jobs);
+
+                let mut parents = parents.to_vec();
+                parents.push(scopes.len());
+                scopes.push(Scope {
+                    middleware_req,
+                    middleware_res,
+                    jobs_req,
+                    jobs_res,
+                    middleware_types,
+                    middleware_bounds,
                 });
 
-                let server = darpi::Server::bind(&address).serve(make_svc);
-                server.await
-             }
+                flatten(
+                    group.handlers,
+                    &format!("{}{}", prefix, group_prefix),
+                    &parents,
+                    outer,
+                    scopes,
+                    handlers,
+                )?;
+            }
         }
-    };
+    }
+    Ok(())
+}
 
-    let tokens = quote! {
-        {
-            #route_possibilities
-            #app
-            App::new(#address_value)
+/// `root` wraps every handler, like a group without a prefix.
+/// `outer` is the response middleware run after the one of the groups, see `make_middleware`.
+pub(crate) fn make_handlers(
+    entries: Punctuated<Entry, token::Comma>,
+    root: Option<Scope>,
+    outer: &[proc_macro2::TokenStream],
+) -> Result<HandlerTokens, SynError> {
+    let mut handlers = vec![];
+    let mut scopes = vec![];
+    let parents = match root {
+        Some(root) => {
+            scopes.push(root);
+            vec![0]
         }
+        None => vec![],
     };
-    //panic!("{}", tokens.to_string());
-    Ok(tokens.into())
-}
+    flatten(entries, "", &parents, outer, &mut scopes, &mut handlers)?;
 
-struct HandlerTokens {
-    routes: Vec<proc_macro2::TokenStream>,
-    route_arg_assert: Vec<proc_macro2::TokenStream>,
-    route_arg_assert_def: Vec<proc_macro2::TokenStream>,
-    routes_match: Vec<proc_macro2::TokenStream>,
-    is: Vec<proc_macro2::TokenStream>,
-    body_assert: Vec<proc_macro2::TokenStream>,
-    body_assert_def: Vec<proc_macro2::TokenStream>,
-}
-
-fn make_handlers(handlers: Punctuated<Handler, token::Comma>) -> Result<HandlerTokens, SynError> {
-    let mut is = vec![];
     let mut routes = vec![];
+    let mut route_defs = vec![];
     let mut routes_match = vec![];
+    let mut handler_types = vec![];
+    let mut variant_names = HashSet::new();
     let body_assert = vec![];
     let body_assert_def = vec![];
     let route_arg_assert = vec![];
     let route_arg_assert_def = vec![];
 
-    for el in handlers.iter() {
-        let handler = el
+    for scoped in handlers.iter() {
+        let el = &scoped.handler;
+        let handler_name: Vec<String> = el
             .handler
             .path
             .segments
-            .last()
-            .expect("cannot get handler segment")
-            .ident
-            .clone();
+            .iter()
+            .map(|s| s.ident.to_string())
+            .collect();
 
         let method = el.method.path.segments.to_token_stream();
         let route = el.route.clone();
-        let variant_name = format!("{}{}", handler.clone(), method.clone());
-        let variant_name: String = variant_name
+        // the whole path is used, so handlers with the same name in different modules don't clash
+        let variant_name = format!("{}{}", handler_name.join("__"), method);
+        let mut variant_name: String = variant_name
             .chars()
             .map(|ch| {
                 if ch.is_alphanumeric() {
@@ -473,12 +952,14 @@ fn make_handlers(handlers: Punctuated<Handler, token::Comma>) -> Result<HandlerT
             })
             .collect();
 
+        // the same handler can be bound to more than one route
+        if !variant_names.insert(variant_name.clone()) {
+            variant_name = format!("{}_{}", variant_name, variant_names.len());
+            variant_names.insert(variant_name.clone());
+        }
+
         let variant_name = format_ident!("{}", variant_name);
-        let variant_value = el
-            .handler
-            .path
-            .get_ident()
-            .expect("cannot get handler path ident");
+        let variant_value = &el.handler;
 
         let method_name = el.method.path.segments.last().unwrap();
         // let mut f_name = format_ident!("assert_has_no_path_args_{}", variant_value);
@@ -504,79 +985,98 @@ fn make_handlers(handlers: Punctuated<Handler, token::Comma>) -> Result<HandlerT
             // });
         }
 
-        is.push(quote! {
-            RoutePossibilities::#variant_name => {
-                let req_route = darpi::ReqRoute::try_from(route).unwrap();
-                let def_route = darpi::Route::try_from(#route).unwrap();
-                if def_route == req_route && method == #method.as_str() {
-                    let args = req_route.extract_args(&def_route).unwrap();
-                    return Some((req_route, args));
-                }
-                None
-            }
+        route_defs.push(RouteDef {
+            variant: quote! {#variant_name},
+            handler: handler_name.join("::"),
+            route: scoped.route.clone(),
+            method: quote! {#method},
+            method_name: method_name.ident.to_string(),
+            span: scoped.span,
         });
 
-        let route_str = route.to_token_stream().to_string();
-        routes.push((
-            quote! {
-                #variant_name
-            },
-            route_str,
-        ));
+        // group middleware wraps the handler, the outermost group runs first on the request
+        // and last on the response
+        let scopes: Vec<&Scope> = scoped.scopes.iter().map(|i| &scopes[*i]).collect();
+        let scope_req: Vec<proc_macro2::TokenStream> = scopes
+            .iter()
+            .map(|scope| {
+                let middleware_req = &scope.middleware_req;
+                let jobs_req = &scope.jobs_req;
+                quote! {
+                    #(#middleware_req )*
+                    #(#jobs_req )*
+                }
+            })
+            .collect();
+        let scope_res: Vec<proc_macro2::TokenStream> = scopes
+            .iter()
+            .rev()
+            .filter(|scope| !scope.middleware_res.is_empty() || !scope.jobs_res.is_empty())
+            .map(|scope| {
+                let middleware_res = &scope.middleware_res;
+                let jobs_res = &scope.jobs_res;
+                quote! {
+                    if let Ok(mut rb) = rb.as_mut() {
+                        #(#middleware_res )*
+                        #(#jobs_res )*
+                    }
+                }
+            })
+            .collect();
 
+        routes.push(quote! {
+            #variant_name
+        });
+
+        handler_types.push(el.handler.to_token_stream());
         routes_match.push(quote! {
             RoutePossibilities::#variant_name => {
+                #(#scope_req )*
+                let catch = darpi::panic::Catch::from_parts(&parts);
+                let timer = darpi::timeout::Timer::from_parts(&parts);
                 let args = darpi::Args{
                     request_parts: &mut parts,
                     container: inner_module.clone(),
                     body: body,
-                    route_args: handler.1.1,
+                    route_args: handler.1,
                 };
-                Handler::call(&#variant_value, args).await
+                #[allow(unused_mut)]
+                let mut rb = catch.run(timer.call(&#variant_value, args)).await;
+                #(#scope_res )*
+                rb
             }
         });
     }
 
-    routes.sort_by(|left, right| {
-        let left_matches: Vec<usize> = left.1.match_indices('{').map(|t| t.0).collect();
-
-        if left_matches.is_empty() {
-            return Ordering::Less;
-        }
-
-        let left_count = left_matches.iter().fold(0, |acc, a| acc + a);
-        let right_matches: Vec<usize> = right.1.match_indices('{').map(|t| t.0).collect();
-
-        if right_matches.is_empty() {
-            return Ordering::Greater;
-        }
-
-        let right_count = right_matches.iter().fold(0, |acc, a| acc + a);
+    let matcher = make_matcher(&route_defs)?;
 
-        if left_matches.len() + left_count > right_matches.len() + right_count {
-            return Ordering::Less;
-        }
-
-        Ordering::Greater
-    });
-
-    let routes: Vec<proc_macro2::TokenStream> = routes.into_iter().map(|(ts, _)| ts).collect();
+    let mut state_checks = handler_types.clone();
+    let mut middleware_bounds = vec![];
+    for scope in &scopes {
+        state_checks.extend(scope.middleware_types.iter().cloned());
+        middleware_bounds.extend(scope.middleware_bounds.iter().cloned());
+    }
 
     Ok(HandlerTokens {
         routes,
+        handler_types,
+        state_checks,
+        middleware_bounds,
         route_arg_assert,
         route_arg_assert_def,
         routes_match,
-        is,
+        matcher,
         body_assert,
         body_assert_def,
+        route_defs,
     })
 }
 
 #[derive(Debug)]
 pub(crate) enum Address {
     Lit(LitStr),
-    Ident(Ident),
+    /// Like a variable or a field of the config
+    Expr(Box<Expr>),
 }
 
 // impl ToTokens for Address {
@@ -594,8 +1094,8 @@ impl Parse for Address {
             let lit_str: LitStr = input.parse()?;
             return Ok(Address::Lit(lit_str));
         }
-        let ident: Ident = input.parse()?;
-        Ok(Address::Ident(ident))
+        let expr: Expr = input.parse()?;
+        Ok(Address::Expr(Box::new(expr)))
     }
 }
 
@@ -663,6 +1163,121 @@ impl Parse for Container {
     }
 }
 
+/// Options set through the builder methods of the same name, like `cpu_pool: { threads: 4 }`
+#[derive(Debug)]
+pub(crate) struct Options {
+    options: Vec<(Ident, Expr)>,
+}
+
+impl Options {
+    fn parse(input: ParseStream, allowed: &[&str]) -> SynResult<Self> {
+        let _: Ident = input.parse()?;
+        let _: token::Colon = input.parse()?;
+
+        let content;
+        let _ = braced!(content in input);
+        let mut options: Vec<(Ident, Expr)> = vec![];
+
+        while !content.is_empty() {
+            if content.peek(token::Comma) {
+                let _: token::Comma = content.parse()?;
+            }
+
+            let key: Ident = content.parse()?;
+            let _: token::Colon = content.parse()?;
+
+            if !allowed.iter().any(|a| key == a) {
+                let allowed: Vec<String> = allowed.iter().map(|a| format!("`{}`", a)).collect();
+                return Err(Error::new_spanned(
+                    key.clone(),
+                    format!(
+                        "unknown key: `{}`. Only {} are allowed",
+                        key,
+                        allowed.join(", ")
+                    ),
+                ));
+            }
+            if options.iter().any(|(k, _)| *k == key) {
+                return Err(Error::new_spanned(
+                    key.clone(),
+                    format!("duplicate key: `{}`", key),
+                ));
+            }
+
+            options.push((key, content.parse()?));
+        }
+
+        Ok(Options { options })
+    }
+}
+
+/// Where the tls certificates come from.
+/// Either PEM files or a component implementing `darpi::tls::CertificateProvider`.
+#[derive(Debug)]
+pub(crate) enum Tls {
+    Files { cert: Box<Expr>, key: Box<Expr> },
+    Provider(Box<syn::Type>),
+}
+
+impl Parse for Tls {
+    fn parse(input: ParseStream) -> SynResult<Self> {
+        let name: Ident = input.parse()?;
+        let _: token::Colon = input.parse()?;
+
+        let mut cert: Option<Box<Expr>> = None;
+        let mut key: Option<Box<Expr>> = None;
+        let mut provider: Option<Box<syn::Type>> = None;
+
+        let content;
+        let _ = braced!(content in input);
+
+        while !content.is_empty() {
+            if content.peek(token::Comma) {
+                let _: token::Comma = content.parse()?;
+            }
+
+            let k: Ident = content.parse()?;
+            let _: token::Colon = content.parse()?;
+
+            if k == "cert" {
+                cert = Some(content.parse()?);
+                continue;
+            }
+            if k == "key" {
+                key = Some(content.parse()?);
+                continue;
+            }
+            if k == "provider" {
+                provider = Some(content.parse()?);
+                continue;
+            }
+
+            return Err(Error::new_spanned(
+                k.clone(),
+                format!(
+                    "unknown key: `{}`. Only `cert`, `key` and `provider` are allowed",
+                    k
+                ),
+            ));
+        }
+
+        match (cert, key, provider) {
+            (Some(cert), Some(key), None) => Ok(Tls::Files { cert, key }),
+            (None, None, Some(provider)) => Ok(Tls::Provider(provider)),
+            (None, None, None) => Err(Error::new_spanned(
+                name,
+                "missing `cert` and `key` or `provider`",
+            )),
+            (_, _, Some(provider)) => Err(Error::new_spanned(
+                provider,
+                "`provider` cannot be used together with `cert` and `key`",
+            )),
+            (None, _, _) => Err(Error::new_spanned(name, "missing `cert`")),
+            (_, None, _) => Err(Error::new_spanned(name, "missing `key`")),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum Func {
     Call(ExprCall),
@@ -760,25 +1375,95 @@ impl Parse for ReqResArray {
     }
 }
 
+impl ReqResArray {
+    /// The types of the request and response middleware
+    pub fn types(&self) -> Vec<proc_macro2::TokenStream> {
+        self.request
+            .iter()
+            .chain(self.response.iter())
+            .flatten()
+            .map(Func::get_name)
+            .collect()
+    }
+
+    /// The bounds of the middleware, so it can be called with the container `c`.
+    /// The ones called with handler arguments can be generic over them, so
+    /// their types are only known where they are called and they are left out
+    pub fn bounds(&self, c: &proc_macro2::TokenStream) -> Vec<proc_macro2::TokenStream> {
+        let bound = |f: &Func, tr: proc_macro2::TokenStream| match f {
+            Func::Path(p) => Some(quote! {
+                #p: #tr<
+                    #c,
+                    HandlerArgs = <#p as darpi::MiddlewareTypes>::HandlerArgs,
+                    Error = <#p as darpi::MiddlewareTypes>::Error,
+                    Type = <#p as darpi::MiddlewareTypes>::Type,
+                >
+            }),
+            Func::Call(_) => None,
+        };
+        let request = self
+            .request
+            .iter()
+            .flatten()
+            .filter_map(|f| bound(f, quote! {darpi::RequestMiddleware}));
+        let response = self
+            .response
+            .iter()
+            .flatten()
+            .filter_map(|f| bound(f, quote! {darpi::ResponseMiddleware}));
+        request.chain(response).collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {
-    pub(crate) address: Address,
+    pub(crate) address: Vec<Address>,
     pub(crate) container: Option<Container>,
     pub(crate) jobs: Option<ReqResArray>,
     pub(crate) middleware: Option<ReqResArray>,
-    pub(crate) handlers: Punctuated<Handler, token::Comma>,
+    pub(crate) handlers: Punctuated<Entry, token::Comma>,
+    pub(crate) fallback: Option<ExprPath>,
+    pub(crate) mounts: Vec<Mount>,
+    pub(crate) tls: Option<Tls>,
+    pub(crate) cpu_pool: Option<Options>,
+    pub(crate) server: Option<Options>,
+    pub(crate) timeout: Option<Expr>,
+    pub(crate) state: Vec<(syn::Type, Expr)>,
 }
 
+/// The keys of `app!`
+const KEYS: &[&str] = &[
+    "address",
+    "container",
+    "jobs",
+    "middleware",
+    "handlers",
+    "fallback",
+    "mount",
+    "tls",
+    "cpu_pool",
+    "server",
+    "timeout",
+    "state",
+];
+
 impl Parse for Config {
     fn parse(input: ParseStream) -> SynResult<Self> {
         let content;
         let _ = braced!(content in input);
 
-        let mut address: Option<Address> = None;
+        let mut address: Option<Vec<Address>> = None;
         let mut container: Option<Container> = None;
         let mut jobs: Option<ReqResArray> = None;
         let mut middleware: Option<ReqResArray> = None;
-        let mut handlers: Option<Punctuated<Handler, token::Comma>> = None;
+        let mut handlers: Option<Punctuated<Entry, token::Comma>> = None;
+        let mut fallback: Option<ExprPath> = None;
+        let mut mounts: Vec<Mount> = vec![];
+        let mut tls: Option<Tls> = None;
+        let mut cpu_pool: Option<Options> = None;
+        let mut server: Option<Options> = None;
+        let mut timeout: Option<Expr> = None;
+        let mut state: Vec<(syn::Type, Expr)> = vec![];
 
         while !content.is_empty() {
             if content.peek(token::Comma) {
@@ -790,7 +1475,14 @@ impl Parse for Config {
             if key == "address" {
                 let _: Ident = content.parse()?;
                 let _: token::Colon = content.parse()?;
-                let a: Address = content.parse()?;
+                let a = if content.peek(token::Bracket) {
+                    let list;
+                    let _ = bracketed!(list in content);
+                    let a: Punctuated<Address, token::Comma> = Punctuated::parse_terminated(&list)?;
+                    a.into_iter().collect()
+                } else {
+                    vec![content.parse()?]
+                };
                 address = Some(a);
                 continue;
             }
@@ -799,6 +1491,67 @@ impl Parse for Config {
                 container = Some(c);
                 continue;
             }
+            if key == "cpu_pool" {
+                let p = Options::parse(&content, &["threads", "thread_name", "stack_size"])?;
+                cpu_pool = Some(p);
+                continue;
+            }
+            if key == "server" {
+                let s = Options::parse(
+                    &content,
+                    &[
+                        "keep_alive",
+                        "keep_alive_timeout",
+                        "header_read_timeout",
+                        "http2_only",
+                        "max_connections",
+                        "nodelay",
+                    ],
+                )?;
+                server = Some(s);
+                continue;
+            }
+            if key == "timeout" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let t: Expr = content.parse()?;
+                timeout = Some(t);
+                continue;
+            }
+            if key == "state" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let st;
+                let _ = braced!(st in content);
+                while !st.is_empty() {
+                    if st.peek(token::Comma) {
+                        let _: token::Comma = st.parse()?;
+                        if st.is_empty() {
+                            break;
+                        }
+                    }
+                    let ttype: syn::Type = st.parse()?;
+                    let _: token::Colon = st.parse()?;
+                    let value: Expr = st.parse()?;
+                    let name = ttype.to_token_stream().to_string();
+                    if state
+                        .iter()
+                        .any(|(t, _)| t.to_token_stream().to_string() == name)
+                    {
+                        return Err(Error::new_spanned(
+                            ttype,
+                            format!("duplicate state: `{}`", name),
+                        ));
+                    }
+                    state.push((ttype, value));
+                }
+                continue;
+            }
+            if key == "tls" {
+                let t: Tls = content.parse()?;
+                tls = Some(t);
+                continue;
+            }
             if key == "jobs" {
                 let j: ReqResArray = content.parse()?;
                 jobs = Some(j);
@@ -815,42 +1568,43 @@ impl Parse for Config {
                 let _: token::Colon = content.parse()?;
                 let br;
                 let _ = bracketed!(br in content);
-                let h: Punctuated<Handler, token::Comma> = Punctuated::parse(&br)?;
-
-                let mut handler_validation = HashMap::new();
-
-                for h in h.iter() {
-                    let key = format!(
-                        "{}{}",
-                        h.route.lit.to_token_stream(),
-                        h.method.path.to_token_stream()
-                    );
+                let h: Punctuated<Entry, token::Comma> = Punctuated::parse(&br)?;
+                handlers = Some(h);
+                continue;
+            }
 
-                    if handler_validation.get(&key).is_some() {
-                        return Err(SynError::new(
-                            h.brace.span,
-                            "identical handler already defined",
-                        ));
-                    }
-                    handler_validation.insert(key, ());
-                }
+            if key == "mount" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let br;
+                let _ = bracketed!(br in content);
+                let m: Punctuated<Mount, token::Comma> = Punctuated::parse(&br)?;
+                mounts.extend(m);
+                continue;
+            }
 
-                handlers = Some(h);
+            if key == "fallback" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let f: ExprPath = content.parse()?;
+                fallback = Some(f);
                 continue;
             }
 
+            let allowed: Vec<String> = KEYS.iter().map(|k| format!("`{}`", k)).collect();
             return Err(Error::new_spanned(
                 key.clone(),
                 format!(
-                    "unknown key: `{}`. Only `route`, `handler` and `method` are allowed",
-                    key
+                    "unknown key: `{}`. Only {} are allowed",
+                    key,
+                    allowed.join(", ")
                 ),
             ));
         }
 
         let address = match address {
-            Some(r) => r,
-            None => return Err(SynError::new(Span::call_site(), "missing `address`")),
+            Some(r) if !r.is_empty() => r,
+            _ => return Err(SynError::new(Span::call_site(), "missing `address`")),
         };
 
         let handlers = match handlers {
@@ -864,13 +1618,157 @@ impl Parse for Config {
             jobs,
             middleware,
             handlers,
+            fallback,
+            mounts,
+            tls,
+            cpu_pool,
+            server,
+            timeout,
+            state,
         });
     }
 }
 
+/// Routes generated by `routes!`, served under `prefix`
+#[derive(Debug)]
+pub(crate) struct Mount {
+    prefix: LitStr,
+    routes: Expr,
+}
+
+impl Parse for Mount {
+    fn parse(input: ParseStream) -> SynResult<Self> {
+        let tuple: syn::ExprTuple = input.parse()?;
+        if tuple.elems.len() != 2 {
+            return Err(Error::new_spanned(
+                tuple,
+                "expected a `(prefix, routes)` tuple",
+            ));
+        }
+
+        let mut elems = tuple.elems.into_iter();
+        let prefix = match elems.next() {
+            Some(Expr::Lit(ExprLit {
+                lit: Lit::Str(s), ..
+            })) => s,
+            Some(e) => return Err(Error::new_spanned(e, "prefix must be a string literal")),
+            None => unreachable!(),
+        };
+
+        let value = prefix.value();
+        if !value.starts_with('/') || value.ends_with('/') {
+            return Err(Error::new_spanned(
+                prefix,
+                "mount prefix must start with `/` and must not end with it",
+            ));
+        }
+
+        Ok(Mount {
+            prefix,
+            routes: elems.next().unwrap(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Entry {
+    Handler(Handler),
+    Group(Group),
+}
+
+impl Parse for Entry {
+    fn parse(input: ParseStream) -> SynResult<Self> {
+        let fork = input.fork();
+        let content;
+        let _ = braced!(content in fork);
+        let key: Ident = content.parse()?;
+
+        if key == "group" {
+            return Ok(Entry::Group(input.parse()?));
+        }
+        Ok(Entry::Handler(input.parse()?))
+    }
+}
+
+/// Handlers sharing a route prefix, middleware and jobs.
+/// Groups can be nested and their prefixes are concatenated.
+#[derive(Debug)]
+pub(crate) struct Group {
+    prefix: LitStr,
+    jobs: Option<ReqResArray>,
+    middleware: Option<ReqResArray>,
+    handlers: Punctuated<Entry, token::Comma>,
+}
+
+impl Parse for Group {
+    fn parse(input: ParseStream) -> SynResult<Self> {
+        let content;
+        let brace = braced!(content in input);
+        let mut prefix: Option<LitStr> = None;
+        let mut jobs: Option<ReqResArray> = None;
+        let mut middleware: Option<ReqResArray> = None;
+        let mut handlers: Option<Punctuated<Entry, token::Comma>> = None;
+
+        while !content.is_empty() {
+            if content.peek(token::Comma) {
+                let _: token::Comma = content.parse()?;
+            }
+
+            let key = content.fork().parse::<Ident>()?;
+
+            if key == "group" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                prefix = Some(content.parse()?);
+                continue;
+            }
+            if key == "jobs" {
+                jobs = Some(content.parse()?);
+                continue;
+            }
+            if key == "middleware" {
+                middleware = Some(content.parse()?);
+                continue;
+            }
+            if key == "handlers" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let br;
+                let _ = bracketed!(br in content);
+                handlers = Some(Punctuated::parse(&br)?);
+                continue;
+            }
+
+            return Err(Error::new_spanned(
+                key.clone(),
+                format!(
+                    "unknown key: `{}`. Only `group`, `middleware`, `jobs` and `handlers` are allowed",
+                    key
+                ),
+            ));
+        }
+
+        let prefix = match prefix {
+            Some(r) => r,
+            None => return Err(SynError::new(brace.span, "missing `group`")),
+        };
+
+        let handlers = match handlers {
+            Some(r) => r,
+            None => return Err(SynError::new(brace.span, "missing `handlers`")),
+        };
+
+        Ok(Group {
+            prefix,
+            jobs,
+            middleware,
+            handlers,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Handler {
-    brace: token::Brace,
     route: ExprLit,
     method: ExprPath,
     handler: ExprPath,
@@ -932,7 +1830,6 @@ impl Parse for Handler {
         };
 
         return Ok(Handler {
-            brace,
             route,
             method,
             handler,
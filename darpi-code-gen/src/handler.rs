@@ -8,7 +8,7 @@ use syn::parse::Parse;
 use syn::punctuated::Punctuated;
 use syn::{
     braced, parse::ParseStream, parse_macro_input, token, Error, Expr, ExprLit, FnArg, ItemFn,
-    PatType, Path, PathSegment, Result as SynResult, Type, TypePath,
+    LitStr, PatType, Path, PathSegment, Result as SynResult, Type, TypePath,
 };
 
 pub(crate) const HAS_PATH_ARGS_PREFIX: &str = "HasPathArgs";
@@ -31,6 +31,10 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
     let has_no_path_args = format_ident!("{}_{}", HAS_NO_PATH_ARGS_PREFIX, func_name);
     let mut map = HashMap::new();
     let mut max_middleware_index = None;
+    // built first, as the other arguments may take the request parts
+    let mut first_args = vec![];
+    let mut scoped_bounds = vec![];
+    let mut state_types = vec![];
     let mut dummy_t = quote! {,T};
     let mut module_type = quote! {T};
 
@@ -40,10 +44,16 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
         let args = parse_macro_input!(args as Config);
         Some(args)
     };
+    let middleware_types = args
+        .as_ref()
+        .and_then(|a| a.middleware.as_ref())
+        .map(ReqResArray::types)
+        .unwrap_or_default();
     let ArgsCall {
         mut middleware_call,
         job_call,
         container,
+        timeout,
     } = make_args_call(args);
 
     if let Some(m) = container {
@@ -107,6 +117,29 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
                     }
                     (i, ts)
                 }
+                HandlerArgs::Scoped(id, ts, scoped) => {
+                    scoped_bounds.push(quote! {#scoped: darpi::scope::RequestScoped<#module_type>});
+                    first_args.push(ts);
+                    give_args.push(quote! {#id});
+                    i += 1;
+                    tp.attrs = Default::default();
+                    continue;
+                }
+                HandlerArgs::Header(id, ts) => {
+                    first_args.push(ts);
+                    give_args.push(quote! {#id});
+                    i += 1;
+                    tp.attrs = Default::default();
+                    continue;
+                }
+                HandlerArgs::State(id, ts, state) => {
+                    state_types.push(state);
+                    first_args.push(ts);
+                    give_args.push(quote! {#id});
+                    i += 1;
+                    tp.attrs = Default::default();
+                    continue;
+                }
                 HandlerArgs::Middleware(i, ts, index, ttype) => {
                     if let Some(s) = max_middleware_index {
                         if index > s {
@@ -140,9 +173,17 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
     let dummy_where = if dummy_t.is_empty() {
         quote! {}
     } else {
-        quote! { where T: 'static + Send + Sync}
+        quote! { where T: 'static + Send + Sync, #(#scoped_bounds ,)*}
     };
 
+    let timeout = timeout.map_or(Default::default(), |timeout| {
+        quote! {
+            fn timeout(&self) -> Option<darpi::timeout::Timeout> {
+                Some(darpi::timeout::Timeout::from(#timeout))
+            }
+        }
+    });
+
     let jobs_req = job_call.req;
     let jobs_res = job_call.res;
 
@@ -176,6 +217,7 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
                 #(#middleware_req )*
                 #(#jobs_req )*
 
+               #(#first_args )*
                #(#make_args )*
 
                let mut rb = Self::#func_name(#(#give_args ,)*).await.respond();
@@ -185,6 +227,15 @@ pub(crate) fn make_handler(args: TokenStream, input: TokenStream) -> TokenStream
 
                 Ok(rb)
             }
+
+            #timeout
+        }
+
+        impl<S> darpi::state::UsesState<S> for #func_name
+        where
+            #(S: darpi::state::HasState<#state_types>,)*
+            #(#middleware_types: darpi::state::UsesState<S>,)*
+        {
         }
     };
     //panic!("{}", output.to_string());
@@ -195,13 +246,14 @@ struct ArgsCall {
     middleware_call: MiddlewareCall,
     job_call: JobCall,
     container: Option<Path>,
+    timeout: Option<Expr>,
 }
 
 fn make_args_call(conf: Option<Config>) -> ArgsCall {
-    let (middleware, job, container) = if let Some(args) = conf {
-        (args.middleware, args.jobs, args.container)
+    let (middleware, job, container, timeout) = if let Some(args) = conf {
+        (args.middleware, args.jobs, args.container, args.timeout)
     } else {
-        (None, None, None)
+        (None, None, None, None)
     };
 
     let middleware_call = make_call_middleware(middleware);
@@ -210,6 +262,7 @@ fn make_args_call(conf: Option<Config>) -> ArgsCall {
         middleware_call,
         job_call,
         container,
+        timeout,
     }
 }
 
@@ -318,6 +371,48 @@ fn make_query(
     }
 }
 
+fn make_header(
+    arg_name: &Ident,
+    ttype: &Type,
+    attr: &syn::Attribute,
+) -> Result<proc_macro2::TokenStream, TokenStream> {
+    let (inner, optional) = match crate::option_type(ttype) {
+        Some(inner) => (inner, true),
+        None => (ttype.clone(), false),
+    };
+    // without a name, it is the one of the typed header
+    let name = if attr.tokens.is_empty() {
+        quote! {<#inner as darpi::request::NamedHeader>::NAME}
+    } else {
+        let name: LitStr = attr
+            .parse_args()
+            .map_err(|e| TokenStream::from(e.to_compile_error()))?;
+        quote! {#name}
+    };
+    let missing = if optional {
+        quote! {Ok(None) => None,}
+    } else {
+        quote! {
+            Ok(None) => return Ok(darpi::request::assert_respond_err::<darpi::request::HeaderError, darpi::request::HeaderError>(
+                darpi::request::HeaderError::Missing(#name),
+            )),
+        }
+    };
+    let found = if optional {
+        quote! {Some(h)}
+    } else {
+        quote! {h}
+    };
+
+    Ok(quote! {
+        let #arg_name: #ttype = match darpi::request::header::<#inner>(&args.request_parts.headers, #name) {
+            Ok(Some(h)) => #found,
+            #missing
+            Err(e) => return Ok(darpi::request::assert_respond_err::<darpi::request::HeaderError, darpi::request::HeaderError>(e)),
+        };
+    })
+}
+
 fn make_path_args(arg_name: &Ident, last: &PathSegment) -> proc_macro2::TokenStream {
     quote! {
         let json_args = match darpi::serde_json::to_string(&args.route_args) {
@@ -370,6 +465,9 @@ enum HandlerArgs {
     Body(Ident, proc_macro2::TokenStream),
     Path(Ident, proc_macro2::TokenStream),
     Module(Ident, proc_macro2::TokenStream),
+    Scoped(Ident, proc_macro2::TokenStream, Type),
+    State(Ident, proc_macro2::TokenStream, Type),
+    Header(Ident, proc_macro2::TokenStream),
     Middleware(Ident, proc_macro2::TokenStream, u64, Type),
     JobChan(Ident, proc_macro2::TokenStream),
 }
@@ -459,11 +557,37 @@ fn make_handler_args(
             }
 
             if attr_ident == "inject" {
+                if let Some(scoped) = crate::scoped_type(ttype) {
+                    let method_resolve = quote! {
+                        let #arg_name: #ttype = match darpi::scope::Scoped::get(&*args.request_parts, args.container.clone()).await {
+                            Ok(s) => s,
+                            Err(e) => return Ok(e.respond_err()),
+                        };
+                    };
+                    return Ok(HandlerArgs::Scoped(arg_name, method_resolve, scoped));
+                }
                 let method_resolve = quote! {
                     let #arg_name: #ttype = #module_ident.resolve();
                 };
                 return Ok(HandlerArgs::Module(arg_name, method_resolve));
             }
+
+            if attr_ident == "header" {
+                let res = make_header(&arg_name, ttype, attr)?;
+                return Ok(HandlerArgs::Header(arg_name, res));
+            }
+
+            if attr_ident == "state" {
+                let state = crate::state_type(ttype)
+                    .map_err(|e| TokenStream::from(e.to_compile_error()))?;
+                let method_resolve = quote! {
+                    let #arg_name: #ttype = match darpi::state::State::of::<#state>(&args.request_parts) {
+                        Ok(s) => s,
+                        Err(e) => return Ok(e.respond_err()),
+                    };
+                };
+                return Ok(HandlerArgs::State(arg_name, method_resolve, state));
+            }
         }
 
         if attr_ident.len() == 2 {
@@ -750,6 +874,7 @@ pub struct Config {
     pub(crate) container: Option<syn::Path>,
     pub(crate) jobs: Option<ReqResArray>,
     pub(crate) middleware: Option<ReqResArray>,
+    pub(crate) timeout: Option<Expr>,
 }
 
 impl Parse for Config {
@@ -760,6 +885,7 @@ impl Parse for Config {
         let mut container: Option<syn::Path> = None;
         let mut jobs: Option<ReqResArray> = None;
         let mut middleware: Option<ReqResArray> = None;
+        let mut timeout: Option<Expr> = None;
 
         while !content.is_empty() {
             if content.peek(token::Comma) {
@@ -785,6 +911,13 @@ impl Parse for Config {
                 middleware = Some(m);
                 continue;
             }
+            if key == "timeout" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let t: Expr = content.parse()?;
+                timeout = Some(t);
+                continue;
+            }
 
             return Err(Error::new_spanned(
                 key.clone(),
@@ -799,6 +932,7 @@ impl Parse for Config {
             container,
             jobs,
             middleware,
+            timeout,
         });
     }
 }
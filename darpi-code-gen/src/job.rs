@@ -222,6 +222,10 @@ fn make_args(func: &mut ItemFn, job_type: &String) -> Result<CallArgs, TokenStre
                     }
                     (false, i.to_token_stream(), ts)
                 }
+                HandlerArg::Scoped(..) => {
+                    unreachable!("request scoped values are not injected into jobs")
+                }
+                HandlerArg::State(..) => unreachable!("state is not given to jobs"),
             };
 
             if is_h {
@@ -6,11 +6,16 @@ mod job;
 mod logger;
 mod middleware;
 mod request;
+mod router;
+mod routes;
 
 use proc_macro::TokenStream;
 use proc_macro2::Ident;
 use quote::{format_ident, quote, ToTokens};
-use syn::{parse, parse_macro_input, Error, ExprLit, ItemStruct, Pat, PatType, Type};
+use syn::{
+    parse, parse_macro_input, Error, ExprLit, GenericArgument, ItemStruct, Pat, PatType,
+    PathArguments, Type,
+};
 
 #[proc_macro_attribute]
 pub fn from_path(_: TokenStream, input: TokenStream) -> TokenStream {
@@ -66,6 +71,15 @@ pub fn app(input: TokenStream) -> TokenStream {
     }
 }
 
+#[proc_macro]
+pub fn routes(input: TokenStream) -> TokenStream {
+    let config = parse_macro_input!(input as routes::Config);
+    match routes::make_routes(config) {
+        Ok(r) => r,
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
 enum HandlerArg {
     Handler(
         bool,
@@ -75,9 +89,48 @@ enum HandlerArg {
         proc_macro2::TokenStream,
     ),
     Module(Ident, proc_macro2::TokenStream),
+    /// A `Scoped<T>` and its `T`
+    Scoped(Ident, proc_macro2::TokenStream, Type),
+    /// An `Arc<T>` of the app state and its `T`
+    State(Ident, proc_macro2::TokenStream, Type),
     Permanent(proc_macro2::TokenStream, proc_macro2::TokenStream),
 }
 
+/// The `T` of `wrapper<T>`, like `Scoped<T>`
+fn wrapped_type(ttype: &Type, wrapper: &str) -> Option<Type> {
+    let tp = match ttype {
+        Type::Path(tp) => tp,
+        _ => return None,
+    };
+    let last = tp.path.segments.last()?;
+    if last.ident != wrapper {
+        return None;
+    }
+    match &last.arguments {
+        PathArguments::AngleBracketed(ab) if ab.args.len() == 1 => match ab.args.first() {
+            Some(GenericArgument::Type(t)) => Some(t.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The `T` of an injected `Scoped<T>`, which is request scoped instead of a component
+fn scoped_type(ttype: &Type) -> Option<Type> {
+    wrapped_type(ttype, "Scoped")
+}
+
+/// The `T` of an `Option<T>`
+fn option_type(ttype: &Type) -> Option<Type> {
+    wrapped_type(ttype, "Option")
+}
+
+/// The `T` of a `#[state]` argument, which has to be an `Arc<T>`
+fn state_type(ttype: &Type) -> Result<Type, Error> {
+    wrapped_type(ttype, "Arc")
+        .ok_or_else(|| Error::new_spanned(ttype, "state arguments are expected to be `Arc<T>`"))
+}
+
 fn make_handler_arg(
     tp: &PatType,
     i: u32,
@@ -163,11 +216,41 @@ fn make_handler_arg(
         return Ok(HandlerArg::Handler(false, bounds, arg_name, t_type, res));
     }
     if attr_ident == "inject" {
+        if let Some(scoped) = scoped_type(ttype) {
+            if !is_request || name == "Job" {
+                return Err(Error::new_spanned(
+                    ttype,
+                    "request scoped values can only be injected into handlers and request middleware",
+                )
+                .to_compile_error()
+                .into());
+            }
+            let method_resolve = quote! {
+                let #arg_name: #ttype = darpi::scope::Scoped::get(&*p, #module_ident.clone()).await?;
+            };
+            return Ok(HandlerArg::Scoped(arg_name, method_resolve, scoped));
+        }
         let method_resolve = quote! {
             let #arg_name: #ttype = #module_ident.resolve();
         };
         return Ok(HandlerArg::Module(arg_name, method_resolve));
     }
+    if attr_ident == "state" {
+        if !is_request || name == "Job" {
+            return Err(Error::new_spanned(
+                attr_ident,
+                "state can only be given to handlers and request middleware",
+            )
+            .to_compile_error()
+            .into());
+        }
+        let state = state_type(ttype).map_err(|e| TokenStream::from(e.to_compile_error()))?;
+        // checked by `app!`, so it is only missing outside of an app
+        let method_resolve = quote! {
+            let #arg_name: #ttype = darpi::state::State::of::<#state>(&*p)?;
+        };
+        return Ok(HandlerArg::State(arg_name, method_resolve, state));
+    }
 
     Err(Error::new_spanned(
         attr_ident,
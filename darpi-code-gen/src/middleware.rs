@@ -35,6 +35,8 @@ pub(crate) fn make_middleware(args: TokenStream, input: TokenStream) -> TokenStr
         make,
         give,
         where_clause,
+        scoped_types,
+        state_types,
         handler_types,
         handler_bounds,
         handler_gen_types,
@@ -96,49 +98,92 @@ pub(crate) fn make_middleware(args: TokenStream, input: TokenStream) -> TokenStr
         }
     };
 
+    // the errors of request scoped values are converted into the error of the middleware
+    let scoped_bounds: Vec<TokenStream2> = scoped_types
+        .iter()
+        .map(|t| {
+            quote! {
+                #t: darpi::scope::RequestScoped<M>,
+                #e: From<<#t as darpi::scope::RequestScoped<M>>::Error>
+            }
+        })
+        .collect();
+
+    // a missing state value is converted into the error of the middleware, like a scoped one
+    let state_bound = if state_types.is_empty() {
+        Default::default()
+    } else {
+        quote! {#e: From<darpi::state::MissingState>,}
+    };
+
     let handler_t = if handler_types.len() == 1 {
         quote! {#(#handler_types)*}
     } else {
         quote! {( #(#handler_types ,)* )}
     };
 
-    let (gen_params, with_brackets, bounds, phantom_data) = if handler_bounds.is_empty() {
-        (
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            quote! {;},
-        )
-    } else {
-        let mut bound = vec![];
-        let mut phantom_data = vec![];
-
-        for i in 0..handler_bounds.len() {
-            if let Some(id) = handler_gen_types.get(i) {
-                let hb = handler_bounds[i].clone();
-                bound.push(quote! {#id: #(#hb +)*});
-                let m_id = format_ident!("_marker{}", i);
-                phantom_data.push(quote! {#m_id: std::marker::PhantomData<#id>});
+    let (gen_params, with_brackets, with_defaults, bounds, phantom_data) =
+        if handler_bounds.is_empty() {
+            (
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                quote! {;},
+            )
+        } else {
+            let mut bound = vec![];
+            let mut phantom_data = vec![];
+
+            for i in 0..handler_bounds.len() {
+                if let Some(id) = handler_gen_types.get(i) {
+                    let hb = handler_bounds[i].clone();
+                    bound.push(quote! {#id: #(#hb +)*});
+                    let m_id = format_ident!("_marker{}", i);
+                    phantom_data.push(quote! {#m_id: std::marker::PhantomData<#id>});
+                }
             }
+
+            (
+                quote! {, #(#handler_gen_types ,)*},
+                quote! {<#(#handler_gen_types ,)*>},
+                quote! {<#(#handler_gen_types = () ,)*>},
+                quote! { #(#bound ,)*},
+                quote! {{#(#phantom_data ,)*}},
+            )
+        };
+
+    // handlers and `app!` name the middleware without its generic types,
+    // which are only known where it is called, so they default to `()` here
+    let uses_state = quote! {
+        #[allow(non_camel_case_types)]
+        impl<S #gen_params> darpi::state::UsesState<S> for #name#with_brackets
+        where
+            #(S: darpi::state::HasState<#state_types>,)*
+        {
         }
 
-        (
-            quote! {, #(#handler_gen_types ,)*},
-            quote! {<#(#handler_gen_types ,)*>},
-            quote! { #(#bound ,)*},
-            quote! {{#(#phantom_data ,)*}},
-        )
+        #[allow(non_camel_case_types)]
+        impl<#(#handler_gen_types ,)*> darpi::MiddlewareTypes for #name#with_brackets
+        where
+            #bounds
+        {
+            type HandlerArgs = #handler_t;
+            type Error = #e;
+            type Type = #k;
+        }
     };
 
     let tokens = match first_arg.as_str() {
         "Request" => {
             quote! {
                 #[allow(non_camel_case_types, missing_docs)]
-                pub struct #name#with_brackets#phantom_data
+                pub struct #name#with_defaults#phantom_data
                 #[allow(non_camel_case_types, missing_docs)]
                 impl#with_brackets #name#with_brackets {
                     #func
                 }
+                #uses_state
 
                 #[allow(non_camel_case_types, missing_docs)]
                 #[darpi::async_trait]
@@ -146,6 +191,8 @@ pub(crate) fn make_middleware(args: TokenStream, input: TokenStream) -> TokenStr
                 where
                     M: 'static + Sync + Send #where_module,
                     #bounds
+                    #(#scoped_bounds ,)*
+                    #state_bound
                 {
                     type HandlerArgs = #handler_t;
                     type Error = #e;
@@ -166,11 +213,12 @@ pub(crate) fn make_middleware(args: TokenStream, input: TokenStream) -> TokenStr
         "Response" => {
             quote! {
                 #[allow(non_camel_case_types, missing_docs)]
-                pub struct #name#with_brackets#phantom_data
+                pub struct #name#with_defaults#phantom_data
                 #[allow(non_camel_case_types, missing_docs)]
                 impl#with_brackets #name#with_brackets {
                     #func
                 }
+                #uses_state
 
                 #[allow(non_camel_case_types, missing_docs)]
                 #[darpi::async_trait]
@@ -213,6 +261,8 @@ struct CallArgs {
     make: Vec<TokenStream2>,
     give: Vec<TokenStream2>,
     where_clause: Vec<TokenStream2>,
+    scoped_types: Vec<Type>,
+    state_types: Vec<Type>,
     handler_types: Vec<TokenStream2>,
     handler_bounds: Vec<Vec<TokenStream2>>,
     handler_gen_types: Vec<TokenStream2>,
@@ -227,6 +277,9 @@ fn make_args(func: &mut ItemFn, middleware_type: &String) -> Result<CallArgs, To
     let mut handler_gen_types = vec![];
     let mut handler_bounds = vec![];
     let mut handler_make = vec![];
+    let mut scoped_make = vec![];
+    let mut scoped_types = vec![];
+    let mut state_types = vec![];
 
     let module_ident = format_ident!("{}", MODULE_PREFIX);
 
@@ -260,6 +313,23 @@ fn make_args(func: &mut ItemFn, middleware_type: &String) -> Result<CallArgs, To
                     }
                     (false, i.to_token_stream(), ts)
                 }
+                // built first, as the other arguments may take the request parts
+                HandlerArg::Scoped(id, ts, scoped) => {
+                    scoped_types.push(scoped);
+                    scoped_make.push(ts);
+                    give.push(quote! {#id});
+                    i += 1;
+                    tp.attrs = Default::default();
+                    continue;
+                }
+                HandlerArg::State(id, ts, state) => {
+                    state_types.push(state);
+                    scoped_make.push(ts);
+                    give.push(quote! {#id});
+                    i += 1;
+                    tp.attrs = Default::default();
+                    continue;
+                }
             };
 
             if is_h {
@@ -285,11 +355,14 @@ fn make_args(func: &mut ItemFn, middleware_type: &String) -> Result<CallArgs, To
     });
 
     make.append(&mut handler_make);
+    scoped_make.append(&mut make);
 
     Ok(CallArgs {
-        make,
+        make: scoped_make,
         give,
         where_clause,
+        scoped_types,
+        state_types,
         handler_types,
         handler_bounds,
         handler_gen_types,
@@ -0,0 +1,551 @@
+use darpi_route::{Constraint, Route, Token};
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use syn::Error;
+
+/// A single `route + method` pair registered in `app!`
+pub(crate) struct RouteDef {
+    pub variant: TokenStream2,
+    pub handler: String,
+    pub route: String,
+    pub method: TokenStream2,
+    pub method_name: String,
+    pub span: Span,
+}
+
+enum Segment {
+    Static(String),
+    Arg(String, Option<String>, TokenStream2),
+    Wildcard,
+    Tail(String),
+}
+
+impl Segment {
+    /// Segments with the same shape match exactly the same request segments.
+    /// A wildcard and an unconstrained argument share a shape.
+    fn shape(&self) -> String {
+        match self {
+            Self::Static(s) => format!("/{}", s),
+            Self::Arg(_, Some(c), _) => format!("/{{:{}}}", c),
+            Self::Arg(_, None, _) | Self::Wildcard => "/{}".to_string(),
+            Self::Tail(_) => "/{*}".to_string(),
+        }
+    }
+
+    fn constraint(&self) -> Option<Constraint<'_>> {
+        match self {
+            Self::Arg(_, Some(c), _) => Some(Constraint::from(c.as_str())),
+            _ => None,
+        }
+    }
+
+    /// Whether a request segment can match both
+    fn intersects(&self, other: &Segment) -> bool {
+        match (self, other) {
+            (Self::Static(a), Self::Static(b)) => a == b,
+            (Self::Static(s), seg) | (seg, Self::Static(s)) => {
+                !s.is_empty() && seg.constraint().is_none_or(|c| c.is_match(s))
+            }
+            (a, b) => match (a.constraint(), b.constraint()) {
+                (Some(a), Some(b)) => a.overlaps(&b),
+                _ => true,
+            },
+        }
+    }
+}
+
+/// Whether a request path can match both routes
+fn intersects(a: &[Segment], b: &[Segment]) -> bool {
+    match (a.split_first(), b.split_first()) {
+        (None, None) => true,
+        (Some((Segment::Tail(_), _)), Some(_)) | (Some(_), Some((Segment::Tail(_), _))) => true,
+        (Some((x, a)), Some((y, b))) => x.intersects(y) && intersects(a, b),
+        _ => false,
+    }
+}
+
+/// The constraints of two routes, which first differ in constrained arguments,
+/// when a request can match both.
+/// Constrained arguments are tried in the order they were registered,
+/// so which of them handles the request would depend on the order of the routes.
+fn overlapping_constraints<'a>(a: &'a [Segment], b: &'a [Segment]) -> Option<(&'a str, &'a str)> {
+    let diverge = a.iter().zip(b).position(|(a, b)| a.shape() != b.shape())?;
+    let constraints = match (&a[diverge], &b[diverge]) {
+        (Segment::Arg(_, Some(x), _), Segment::Arg(_, Some(y), _)) => (x.as_str(), y.as_str()),
+        _ => return None,
+    };
+    if intersects(a, b) {
+        Some(constraints)
+    } else {
+        None
+    }
+}
+
+struct Endpoint {
+    variant: TokenStream2,
+    method: TokenStream2,
+    method_name: String,
+    args: Vec<(String, Ident)>,
+}
+
+impl Endpoint {
+    fn inserts(&self) -> Vec<TokenStream2> {
+        self.args
+            .iter()
+            .map(|(name, value)| quote! {args.insert(#name, #value);})
+            .collect()
+    }
+
+    /// Returns the route, if the request has its method.
+    /// A `HEAD` handler is only called if no more specific `GET` route answered `HEAD` before,
+    /// just as that route would take precedence for `GET`.
+    fn found(&self) -> TokenStream2 {
+        let variant = &self.variant;
+        let method = &self.method;
+        let inserts = self.inserts();
+
+        let implied = if self.method_name == "HEAD" {
+            quote! {
+                if let Some((variant, args)) = head {
+                    return RouteMatch::Head(variant, args);
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            if *method == #method {
+                #implied
+                #[allow(unused_mut)]
+                let mut args = std::collections::HashMap::new();
+                #(#inserts )*
+                return RouteMatch::Found(RoutePossibilities::#variant, args);
+            }
+        }
+    }
+
+    /// A `GET` route also answers `HEAD`, unless a `HEAD` handler of the same route
+    /// or a more specific route answers it.
+    /// Only checked once all the methods of the route were, so its own `HEAD` handler goes first.
+    fn head(&self) -> TokenStream2 {
+        if self.method_name != "GET" {
+            return quote! {};
+        }
+        let variant = &self.variant;
+        let inserts = self.inserts();
+        quote! {
+            if *method == darpi::Method::HEAD && head.is_none() {
+                #[allow(unused_mut)]
+                let mut args = std::collections::HashMap::new();
+                #(#inserts )*
+                head = Some((RoutePossibilities::#variant, args));
+            }
+        }
+    }
+}
+
+/// Node of the match tree built from all registered routes.
+/// Static children are tried first, then constrained arguments in the order they were registered,
+/// then the unconstrained argument child and tail wildcards come last.
+/// Routes whose constrained arguments can match the same request are rejected,
+/// so the order of registration never decides which handler is called.
+/// `/user/me` always takes precedence over `/user/{id:u64}`, `/user/{id}` and `/user/{*rest}`.
+#[derive(Default)]
+struct Node {
+    statics: Vec<(String, Node)>,
+    constrained: Vec<(String, TokenStream2, Node)>,
+    arg: Option<Box<Node>>,
+    endpoints: Vec<Endpoint>,
+    tails: Vec<Endpoint>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[Segment], endpoint: Endpoint) {
+        let (first, rest) = match segments.split_first() {
+            Some(s) => s,
+            None => {
+                self.endpoints.push(endpoint);
+                return;
+            }
+        };
+
+        let child = match first {
+            Segment::Static(s) => {
+                let pos = match self.statics.iter().position(|(k, _)| k == s) {
+                    Some(pos) => pos,
+                    None => {
+                        self.statics.push((s.clone(), Node::default()));
+                        self.statics.len() - 1
+                    }
+                };
+                &mut self.statics[pos].1
+            }
+            Segment::Arg(_, Some(constraint), check) => {
+                let pos = match self
+                    .constrained
+                    .iter()
+                    .position(|(k, _, _)| k == constraint)
+                {
+                    Some(pos) => pos,
+                    None => {
+                        self.constrained
+                            .push((constraint.clone(), check.clone(), Node::default()));
+                        self.constrained.len() - 1
+                    }
+                };
+                &mut self.constrained[pos].2
+            }
+            Segment::Arg(_, None, _) | Segment::Wildcard => {
+                self.arg.get_or_insert_with(Default::default)
+            }
+            Segment::Tail(_) => {
+                self.tails.push(endpoint);
+                return;
+            }
+        };
+        child.insert(rest, endpoint)
+    }
+
+    fn to_tokens(&self, depth: usize) -> TokenStream2 {
+        let rest = format_ident!("rest_{}", depth);
+        let next_rest = format_ident!("rest_{}", depth + 1);
+        let seg = format_ident!("seg_{}", depth);
+
+        let endpoints: Vec<TokenStream2> = matches(&self.endpoints);
+        let tails: Vec<TokenStream2> = matches(&self.tails);
+
+        let statics: Vec<TokenStream2> = self
+            .statics
+            .iter()
+            .map(|(s, node)| {
+                let child = node.to_tokens(depth + 1);
+                quote! {#s => { #child }}
+            })
+            .collect();
+
+        let constrained: Vec<TokenStream2> = self
+            .constrained
+            .iter()
+            .map(|(_, check, node)| {
+                let child = node.to_tokens(depth + 1);
+                quote! {
+                    if #check {
+                        #child
+                    }
+                }
+            })
+            .collect();
+
+        let arg = self.arg.as_ref().map(|node| {
+            let child = node.to_tokens(depth + 1);
+            quote! {
+                if !#seg.is_empty() {
+                    #child
+                }
+            }
+        });
+
+        let statics = if statics.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                match #seg {
+                    #(#statics ,)*
+                    _ => {}
+                }
+            }
+        };
+
+        let split = if self.statics.is_empty() && self.constrained.is_empty() && self.arg.is_none()
+        {
+            quote! {}
+        } else {
+            quote! {
+                let (#seg, #next_rest) = match rest.find('/') {
+                    Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+                    None => (rest, None),
+                };
+                #statics
+                #(#constrained )*
+                #arg
+            }
+        };
+
+        let tails = if tails.is_empty() {
+            quote! {}
+        } else {
+            let tail = format_ident!("tail_{}", depth);
+            let methods = allowed(&self.tails);
+            quote! {
+                let #tail = rest;
+                #(#tails )*
+                #methods
+            }
+        };
+
+        let descend = if split.is_empty() && tails.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                if let Some(rest) = #rest {
+                    #split
+                    #tails
+                }
+            }
+        };
+
+        let endpoints = if endpoints.is_empty() {
+            quote! {}
+        } else {
+            let methods = allowed(&self.endpoints);
+            quote! {
+                if #rest.is_none() {
+                    #(#endpoints )*
+                    #methods
+                }
+            }
+        };
+
+        quote! {
+            #endpoints
+            #descend
+        }
+    }
+}
+
+/// The methods of the endpoints of a node, before the `HEAD` implied by their `GET`.
+fn matches(endpoints: &[Endpoint]) -> Vec<TokenStream2> {
+    endpoints
+        .iter()
+        .map(Endpoint::found)
+        .chain(endpoints.iter().map(Endpoint::head))
+        .collect()
+}
+
+/// Reached when the path matched a node but none of its methods did.
+/// The methods are collected, because the request may still match another branch of the tree,
+/// and sorted once the whole tree was walked.
+/// `HEAD` is implied by `GET` and `OPTIONS` is always answered.
+fn allowed(endpoints: &[Endpoint]) -> TokenStream2 {
+    let mut methods: Vec<&str> = endpoints.iter().map(|e| e.method_name.as_str()).collect();
+    if methods.contains(&"GET") {
+        methods.push("HEAD");
+    }
+    methods.push("OPTIONS");
+    quote! {
+        for m in &[#(#methods ,)*] {
+            if !allowed.contains(m) {
+                allowed.push(m);
+            }
+        }
+    }
+}
+
+fn constraint_check(seg: &Ident, constraint: Constraint) -> TokenStream2 {
+    match constraint.anchored_regex() {
+        Some(re) => quote! {
+            !#seg.is_empty() && {
+                static RE: darpi::once_cell::sync::Lazy<darpi::regex::Regex> =
+                    darpi::once_cell::sync::Lazy::new(|| {
+                        darpi::regex::Regex::new(#re).expect("regex validated by app!")
+                    });
+                RE.is_match(#seg)
+            }
+        },
+        None => {
+            let ttype = format_ident!("{}", constraint.to_string());
+            quote! {#seg.parse::<#ttype>().is_ok()}
+        }
+    }
+}
+
+fn segments(route: &str, span: Span) -> Result<Vec<Segment>, Error> {
+    let parsed =
+        Route::try_from(route).map_err(|e| Error::new(span, format!("{}: `{}`", e, route)))?;
+
+    let mut tokens = parsed.values.into_iter();
+    match tokens.next() {
+        Some(Token::Slash) => {}
+        _ => {
+            return Err(Error::new(
+                span,
+                format!("route `{}` must start with `/`", route),
+            ))
+        }
+    }
+
+    let mut segments = vec![];
+    let mut current: Option<Segment> = None;
+    let mut names = HashSet::new();
+
+    for token in tokens {
+        let next = match token {
+            Token::Slash => {
+                segments.push(current.take().unwrap_or(Segment::Static(String::new())));
+                continue;
+            }
+            Token::PathSegment(s) => Segment::Static(s.to_string()),
+            Token::Arg(a) => {
+                if !names.insert(a.name) {
+                    return Err(Error::new(
+                        span,
+                        format!("route `{}`: duplicate argument `{}`", route, a.name),
+                    ));
+                }
+                let seg = format_ident!("seg_{}", segments.len());
+                let check = a.constraint.map(|c| constraint_check(&seg, c));
+                Segment::Arg(
+                    a.name.to_string(),
+                    a.constraint.map(|c| c.to_string()),
+                    check.unwrap_or_default(),
+                )
+            }
+            Token::Wildcard => Segment::Wildcard,
+            Token::Tail(name) => {
+                if !names.insert(name) {
+                    return Err(Error::new(
+                        span,
+                        format!("route `{}`: duplicate argument `{}`", route, name),
+                    ));
+                }
+                Segment::Tail(name.to_string())
+            }
+            Token::Error => unreachable!(),
+        };
+
+        if current.is_some() {
+            return Err(Error::new(
+                span,
+                format!(
+                    "route `{}`: a path segment must be either static or a single `{{arg}}`",
+                    route
+                ),
+            ));
+        }
+        current = Some(next);
+    }
+    segments.push(current.unwrap_or(Segment::Static(String::new())));
+
+    Ok(segments)
+}
+
+/// Rejects the routes that match a path under `prefix`.
+/// Mounted routes are only tried when none of the app routes matched the path,
+/// so the mounted routes they match could never be reached.
+pub(crate) fn check_mount(defs: &[RouteDef], prefix: &str, span: Span) -> Result<(), Error> {
+    let mounted = [
+        segments(prefix, span)?,
+        segments(&format!("{}/{{*rest}}", prefix), span)?,
+    ];
+
+    for def in defs {
+        let segments = segments(&def.route, def.span)?;
+        if mounted.iter().any(|m| intersects(&segments, m)) {
+            return Err(Error::new(
+                def.span,
+                format!(
+                    "route `{}` with method `{}` of handler `{}` overlaps with the routes mounted at `{}`. \
+                    The app routes are tried first, so the mounted routes it matches can never be reached",
+                    def.route, def.method_name, def.handler, prefix
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the body of `RoutePossibilities::get_route`.
+/// Every route literal is parsed here, at compile time, and turned into nested
+/// `match` statements over the request path segments.
+/// The request path is only walked once, so matching is linear in its length.
+/// If the path matched, but the method did not, the methods registered for it are returned
+/// with `RouteMatch::MethodNotAllowed`.
+/// `HEAD` and `OPTIONS` requests without an explicit handler are answered with
+/// `RouteMatch::Head` and `RouteMatch::Options`.
+pub(crate) fn make_matcher(defs: &[RouteDef]) -> Result<TokenStream2, Error> {
+    let mut root = Node::default();
+    let mut shapes: HashMap<(String, &str), &RouteDef> = HashMap::new();
+    let mut registered: Vec<(&RouteDef, Vec<Segment>)> = vec![];
+
+    for def in defs {
+        let segments = segments(&def.route, def.span)?;
+
+        let shape: String = segments.iter().map(|s| s.shape()).collect();
+        if let Some(other) = shapes.get(&(shape.clone(), def.method_name.as_str())) {
+            return Err(Error::new(
+                def.span,
+                format!(
+                    "route `{}` with method `{}` of handler `{}` conflicts with route `{}` of handler `{}`. \
+                    They match the same requests, so `{}` can never be reached",
+                    def.route, def.method_name, def.handler, other.route, other.handler, def.handler
+                ),
+            ));
+        }
+        shapes.insert((shape, def.method_name.as_str()), def);
+
+        let overlapping = registered
+            .iter()
+            .filter(|(other, _)| other.method_name == def.method_name)
+            .find_map(|(other, other_segments)| {
+                overlapping_constraints(&segments, other_segments).map(|c| (other, c))
+            });
+        if let Some((other, (constraint, other_constraint))) = overlapping {
+            return Err(Error::new(
+                def.span,
+                format!(
+                    "route `{}` with method `{}` of handler `{}` overlaps with route `{}` of handler `{}`. \
+                    A segment can match both `{}` and `{}`, so the handler would depend on the order of the routes",
+                    def.route, def.method_name, def.handler, other.route, other.handler, constraint, other_constraint
+                ),
+            ));
+        }
+
+        let args = segments
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| match s {
+                Segment::Arg(name, _, _) => Some((name.clone(), format_ident!("seg_{}", i))),
+                Segment::Tail(name) => Some((name.clone(), format_ident!("tail_{}", i))),
+                _ => None,
+            })
+            .collect();
+
+        root.insert(
+            &segments,
+            Endpoint {
+                variant: def.variant.clone(),
+                method: def.method.clone(),
+                method_name: def.method_name.clone(),
+                args,
+            },
+        );
+        registered.push((def, segments));
+    }
+
+    // the root node is never terminal, since every route has at least one segment
+    let tree = root.to_tokens(0);
+
+    Ok(quote! {
+        #[allow(unused_mut)]
+        let mut allowed: Vec<&'static str> = vec![];
+        #[allow(unused_mut)]
+        let mut head = None;
+        let rest_0 = Some(route.strip_prefix('/').unwrap_or(route));
+        #tree
+        // the same order for every path, whichever nodes the methods were collected from
+        const ORDER: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+        allowed.sort_by_key(|m| ORDER.iter().position(|o| o == m).unwrap_or(ORDER.len()));
+        if let Some((variant, args)) = head {
+            RouteMatch::Head(variant, args)
+        } else if allowed.is_empty() {
+            RouteMatch::NotFound
+        } else if *method == darpi::Method::OPTIONS {
+            RouteMatch::Options(allowed)
+        } else {
+            RouteMatch::MethodNotAllowed(allowed)
+        }
+    })
+}
@@ -0,0 +1,198 @@
+use crate::app::{
+    make_dispatch, make_handlers, make_jobs, make_middleware, make_route_possibilities, Entry,
+    HandlerTokens, ReqResArray, Scope,
+};
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{quote, ToTokens};
+use syn::parse::{Error as SynError, Parse, ParseStream, Result as SynResult};
+use syn::parse_quote::ParseQuote;
+use syn::{braced, bracketed, punctuated::Punctuated, token, Error, Ident};
+
+pub(crate) fn make_routes(config: Config) -> Result<TokenStream, SynError> {
+    if config.handlers.is_empty() {
+        return Err(Error::new(Span::call_site(), "no handlers registered"));
+    }
+
+    let middleware_types = config
+        .middleware
+        .as_ref()
+        .map(ReqResArray::types)
+        .unwrap_or_default();
+    let middleware_bounds = config
+        .middleware
+        .as_ref()
+        .map(|m| m.bounds(&quote! {C}))
+        .unwrap_or_default();
+    let (middleware_req, middleware_res) = make_middleware(config.middleware, "g0_", &[]);
+    let (jobs_req, jobs_res) = make_jobs(config.jobs);
+    let root = Scope {
+        middleware_req,
+        middleware_res,
+        jobs_req,
+        jobs_res,
+        middleware_types,
+        middleware_bounds,
+    };
+
+    let HandlerTokens {
+        routes,
+        handler_types,
+        state_checks,
+        middleware_bounds,
+        routes_match,
+        matcher,
+        ..
+    } = make_handlers(config.handlers, Some(root), &[])?;
+
+    let route_possibilities = make_route_possibilities(&routes, &matcher);
+    // unknown routes are left to the app, before dispatching
+    let dispatch = make_dispatch(&routes_match, None, &[], &[]);
+
+    // without an explicit container, the routes work with any container
+    // that every handler and middleware can be called with
+    let (generics, container, bounds) = match config.container {
+        Some(c) => (quote! {}, c.to_token_stream(), quote! {}),
+        None => (
+            quote! {<C>},
+            quote! {C},
+            quote! {
+                where
+                    C: 'static + Sync + Send,
+                    #(#handler_types: for<'b> darpi::Handler<'b, C>,)*
+                    #(#middleware_bounds,)*
+            },
+        ),
+    };
+
+    let name = config.name;
+    let tokens = quote! {
+        /// Routes to be mounted in `app!`
+        pub struct #name;
+
+        // the app it is mounted in checks that it has the state of the routes
+        impl<S> darpi::state::UsesState<S> for #name
+        where
+            #(#state_checks: darpi::state::UsesState<S>,)*
+        {
+        }
+
+        const _: () = {
+            #route_possibilities
+
+            #[darpi::async_trait]
+            impl #generics darpi::Routes<#container> for #name #bounds {
+                async fn call(
+                    &self,
+                    route: &str,
+                    mut parts: &mut darpi::RequestParts,
+                    inner_module: std::sync::Arc<#container>,
+                    mut body: darpi::Body,
+                ) -> Result<Result<darpi::Response<darpi::Body>, std::convert::Infallible>, darpi::Body> {
+                    use darpi::response::ResponderError;
+                    #[allow(unused_imports)]
+                    use darpi::RequestMiddleware;
+                    #[allow(unused_imports)]
+                    use darpi::ResponseMiddleware;
+                    #[allow(unused_imports)]
+                    use darpi::{RequestJobFactory, ResponseJobFactory};
+
+                    let method = parts.method.clone();
+                    let route_match = RoutePossibilities::get_route(route, &method);
+                    if let RouteMatch::NotFound = route_match {
+                        return Err(body);
+                    }
+
+                    Ok(async move {
+                        #dispatch
+                    }.await)
+                }
+            }
+        };
+    };
+    Ok(tokens.into())
+}
+
+#[derive(Debug)]
+pub struct Config {
+    name: Ident,
+    container: Option<syn::Path>,
+    jobs: Option<ReqResArray>,
+    middleware: Option<ReqResArray>,
+    handlers: Punctuated<Entry, token::Comma>,
+}
+
+impl Parse for Config {
+    fn parse(input: ParseStream) -> SynResult<Self> {
+        let content;
+        let _ = braced!(content in input);
+
+        let mut name: Option<Ident> = None;
+        let mut container: Option<syn::Path> = None;
+        let mut jobs: Option<ReqResArray> = None;
+        let mut middleware: Option<ReqResArray> = None;
+        let mut handlers: Option<Punctuated<Entry, token::Comma>> = None;
+
+        while !content.is_empty() {
+            if content.peek(token::Comma) {
+                let _: token::Comma = content.parse()?;
+            }
+
+            let key = content.fork().parse::<Ident>()?;
+
+            if key == "name" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                name = Some(content.parse()?);
+                continue;
+            }
+            if key == "container" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                container = Some(content.parse()?);
+                continue;
+            }
+            if key == "jobs" {
+                jobs = Some(content.parse()?);
+                continue;
+            }
+            if key == "middleware" {
+                middleware = Some(content.parse()?);
+                continue;
+            }
+            if key == "handlers" {
+                let _: Ident = content.parse()?;
+                let _: token::Colon = content.parse()?;
+                let br;
+                let _ = bracketed!(br in content);
+                handlers = Some(Punctuated::parse(&br)?);
+                continue;
+            }
+
+            return Err(Error::new_spanned(
+                key.clone(),
+                format!(
+                    "unknown key: `{}`. Only `name`, `container`, `middleware`, `jobs` and `handlers` are allowed",
+                    key
+                ),
+            ));
+        }
+
+        let name = match name {
+            Some(n) => n,
+            None => return Err(SynError::new(Span::call_site(), "missing `name`")),
+        };
+        let handlers = match handlers {
+            Some(r) => r,
+            None => return Err(SynError::new(Span::call_site(), "missing `handlers`")),
+        };
+
+        Ok(Config {
+            name,
+            container,
+            jobs,
+            middleware,
+            handlers,
+        })
+    }
+}
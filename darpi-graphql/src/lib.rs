@@ -116,6 +116,22 @@ impl MultipartOptionsProvider for MultipartOptionsProviderImpl {
     }
 }
 
+/// The multipart section of a `darpi::config`, for the parameters of `MultipartOptionsProviderImpl`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MultipartConfig {
+    pub max_file_size: Option<usize>,
+    pub max_num_files: Option<usize>,
+}
+
+impl From<&MultipartConfig> for MultipartOptionsProviderImplParameters {
+    fn from(config: &MultipartConfig) -> Self {
+        let mut opts = MultipartOptions::default();
+        opts.max_file_size = config.max_file_size;
+        opts.max_num_files = config.max_num_files;
+        Self { opts }
+    }
+}
+
 #[async_trait]
 impl<C: 'static> FromRequestBodyWithContainer<GraphQLBody<BatchRequest>, GraphQLError, C>
     for GraphQLBody<BatchRequest>
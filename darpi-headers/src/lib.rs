@@ -1,6 +1,8 @@
 mod accept_encoding;
+mod user_agent;
 
 pub use accept_encoding::AcceptEncoding;
+pub use user_agent::UserAgent;
 
 use darpi::header::{HeaderValue, ToStrError};
 use derive_more::Display;
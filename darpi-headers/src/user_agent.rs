@@ -0,0 +1,25 @@
+use darpi::header::{HeaderValue, ToStrError};
+use darpi::request::{FromHeader, NamedHeader};
+use derive_more::Display;
+
+/// The `User-Agent` header, extracted with `#[header] ua: UserAgent`
+#[derive(Display, Debug, Clone, PartialEq)]
+pub struct UserAgent(String);
+
+impl UserAgent {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromHeader for UserAgent {
+    type Error = ToStrError;
+
+    fn from_header(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value.to_str().map(|ua| UserAgent(ua.to_string()))
+    }
+}
+
+impl NamedHeader for UserAgent {
+    const NAME: &'static str = "user-agent";
+}
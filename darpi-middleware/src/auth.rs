@@ -122,12 +122,15 @@ pub trait TokenExtractor: Interface {
     async fn extract(&self, p: &RequestParts) -> Result<Token, Error>;
 }
 
+/// The keys of the `secret` of a `JwtConfig`, set with
+/// `with_component_parameters::<JwtSecretProviderImpl>((&config.jwt).into())`.
+/// There is no secret to fall back to, so building a module without them panics.
 #[derive(Component)]
 #[shaku(interface = JwtSecretProvider)]
 pub struct JwtSecretProviderImpl {
-    #[shaku(default = unimplemented!())]
+    #[shaku(no_default)]
     encoding_key: jsonwebtoken::EncodingKey,
-    #[shaku(default = unimplemented!())]
+    #[shaku(no_default)]
     decoding_key: jsonwebtoken::DecodingKey<'static>,
 }
 
@@ -165,6 +168,48 @@ pub trait JwtAlgorithmProvider: Interface {
     async fn algorithm(&self) -> Algorithm;
 }
 
+/// The jwt section of a `darpi::config`, for the parameters of
+/// `JwtSecretProviderImpl` and `JwtAlgorithmProviderImpl`
+///```rust,ignore
+/// Container::builder()
+///     .with_component_parameters::<JwtSecretProviderImpl>((&config.jwt).into())
+///     .with_component_parameters::<JwtAlgorithmProviderImpl>((&config.jwt).into())
+///     .build()
+///```
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    #[serde(deserialize_with = "secret")]
+    pub secret: String,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+}
+
+/// An empty secret signs tokens anyone can forge, so it is rejected with the config
+fn secret<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let secret = String::deserialize(deserializer)?;
+    if secret.is_empty() {
+        return Err(serde::de::Error::custom("the jwt `secret` can't be empty"));
+    }
+    Ok(secret)
+}
+
+impl From<&JwtConfig> for JwtSecretProviderImplParameters {
+    fn from(config: &JwtConfig) -> Self {
+        Self {
+            encoding_key: EncodingKey::from_secret(config.secret.as_bytes()),
+            decoding_key: DecodingKey::from_secret(config.secret.as_bytes()).into_static(),
+        }
+    }
+}
+
+impl From<&JwtConfig> for JwtAlgorithmProviderImplParameters {
+    fn from(config: &JwtConfig) -> Self {
+        Self {
+            algorithm: config.algorithm,
+        }
+    }
+}
+
 #[derive(Component)]
 #[shaku(interface = JwtTokenCreator)]
 pub struct JwtTokenCreatorImpl {
@@ -18,7 +18,7 @@ use std::convert::TryFrom;
 ///  if the client supports it
 ///```rust
 /// #[tokio::test]
-/// async fn main() -> Result<(), darpi::Error> {
+/// async fn main() -> Result<(), darpi::server::Error> {
 ///     let address = format!("127.0.0.1:{}", 3000);
 ///     app!({
 ///         address: address,
@@ -100,7 +100,7 @@ pub async fn compress(
 /// the handler gets invoked
 ///```rust
 /// #[tokio::test]
-/// async fn main() -> Result<(), darpi::Error> {
+/// async fn main() -> Result<(), darpi::server::Error> {
 ///     let address = format!("127.0.0.1:{}", 3000);
 ///     app!({
 ///         address: address,
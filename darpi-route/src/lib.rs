@@ -1,7 +1,11 @@
-use logos;
 use logos::Logos;
-use std::collections::HashMap;
+use regex::Regex;
+use regex_automata::dfa::{dense, Automaton};
+use regex_automata::util::start;
+use regex_automata::Anchored;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fmt;
 
 #[derive(Logos, Debug, PartialEq)]
 pub enum ReqToken<'a> {
@@ -24,6 +28,8 @@ pub enum ReqToken<'a> {
 #[derive(Debug)]
 pub struct ReqRoute<'a> {
     pub values: Vec<ReqToken<'a>>,
+    path: &'a str,
+    offsets: Vec<usize>,
 }
 
 impl<'a> TryFrom<&'a str> for ReqRoute<'a> {
@@ -32,15 +38,33 @@ impl<'a> TryFrom<&'a str> for ReqRoute<'a> {
     fn try_from(s: &'a str) -> Result<Self, Self::Error> {
         let mut lex = ReqToken::lexer(s);
         let mut values: Vec<ReqToken<'a>> = vec![];
+        let mut offsets = vec![];
 
         while let Some(next) = lex.next() {
             match next {
                 ReqToken::Error => return Err("invalid ReqRoute".to_string()),
-                _ => values.push(next),
+                _ => {
+                    offsets.push(lex.span().start);
+                    values.push(next)
+                }
             }
         }
 
-        Ok(Self { values })
+        Ok(Self {
+            values,
+            path: s,
+            offsets,
+        })
+    }
+}
+
+impl<'a> ReqRoute<'a> {
+    /// the remaining path, slashes included, starting at token `i`
+    fn tail(&self, i: usize) -> &'a str {
+        match self.offsets.get(i) {
+            Some(offset) => &self.path[*offset..],
+            None => &self.path[self.path.len()..],
+        }
     }
 }
 
@@ -50,8 +74,17 @@ pub enum Token<'a> {
     #[token("/")]
     Slash,
 
-    #[regex("[{]([^{}/]+)[}]")]
-    Arg(&'a str),
+    #[regex("[{]([^{}/]+)[}]", |lex| Arg::from_braced(lex.slice()))]
+    Arg(Arg<'a>),
+
+    /// Matches exactly one path segment without capturing it.
+    #[token("*")]
+    Wildcard,
+
+    /// Captures the rest of the path, slashes included.
+    /// Written as `{*name}` or `{name..}` and only allowed as the last segment.
+    /// Holds the bare name, without braces.
+    Tail(&'a str),
 
     // Or regular expressions.
     #[regex(r"[a-zA-Z0-9[.]-_~!$&'()*+,;=:@]+")]
@@ -65,6 +98,166 @@ pub enum Token<'a> {
     Error,
 }
 
+/// A named path argument, optionally restricted by a constraint.
+/// `{id}`, `{id:u64}` and `{slug:[a-z0-9-]+}` are all valid arguments.
+#[derive(Debug, PartialEq)]
+pub struct Arg<'a> {
+    pub name: &'a str,
+    pub constraint: Option<Constraint<'a>>,
+}
+
+impl<'a> Arg<'a> {
+    fn from_braced(s: &'a str) -> Self {
+        let inner = &s[1..s.len() - 1];
+        match inner.find(':') {
+            Some(i) => Self {
+                name: &inner[..i],
+                constraint: Some(Constraint::from(&inner[i + 1..])),
+            },
+            None => Self {
+                name: inner,
+                constraint: None,
+            },
+        }
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        if value.is_empty() {
+            return false;
+        }
+        self.constraint.as_ref().is_none_or(|c| c.is_match(value))
+    }
+}
+
+const CONSTRAINT_TYPES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32",
+    "f64", "bool", "char",
+];
+
+/// Restricts the values a path argument accepts.
+/// Primitive type names are checked by parsing the value,
+/// anything else is treated as a regular expression that must match the whole segment.
+/// Regular expressions cannot contain `{`, `}` or `/`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Constraint<'a> {
+    Type(&'a str),
+    Regex(&'a str),
+}
+
+impl<'a> From<&'a str> for Constraint<'a> {
+    fn from(s: &'a str) -> Self {
+        if CONSTRAINT_TYPES.contains(&s) {
+            return Self::Type(s);
+        }
+        Self::Regex(s)
+    }
+}
+
+impl<'a> Constraint<'a> {
+    /// The regular expression, anchored to match a whole path segment.
+    pub fn anchored_regex(&self) -> Option<String> {
+        match self {
+            Self::Regex(r) => Some(format!("^(?:{})$", r)),
+            Self::Type(_) => None,
+        }
+    }
+
+    /// Checks the value against the constraint.
+    /// Regular expressions are compiled on every call,
+    /// the `app!` macro generates precompiled checks instead.
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            Self::Type(t) => match *t {
+                "u8" => value.parse::<u8>().is_ok(),
+                "u16" => value.parse::<u16>().is_ok(),
+                "u32" => value.parse::<u32>().is_ok(),
+                "u64" => value.parse::<u64>().is_ok(),
+                "u128" => value.parse::<u128>().is_ok(),
+                "usize" => value.parse::<usize>().is_ok(),
+                "i8" => value.parse::<i8>().is_ok(),
+                "i16" => value.parse::<i16>().is_ok(),
+                "i32" => value.parse::<i32>().is_ok(),
+                "i64" => value.parse::<i64>().is_ok(),
+                "i128" => value.parse::<i128>().is_ok(),
+                "isize" => value.parse::<isize>().is_ok(),
+                "f32" => value.parse::<f32>().is_ok(),
+                "f64" => value.parse::<f64>().is_ok(),
+                "bool" => value.parse::<bool>().is_ok(),
+                "char" => value.parse::<char>().is_ok(),
+                _ => false,
+            },
+            Self::Regex(_) => {
+                let re = self.anchored_regex().expect("regex constraint");
+                Regex::new(&re).is_ok_and(|re| re.is_match(value))
+            }
+        }
+    }
+
+    /// A regular expression matching at least the values of the constraint.
+    /// Number types match any digits, regardless of their range.
+    fn pattern(&self) -> &'a str {
+        match self {
+            Self::Regex(r) => r,
+            Self::Type(t) => match *t {
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => r"\+?[0-9]+",
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => r"[+-]?[0-9]+",
+                "f32" | "f64" => {
+                    r"[+-]?(?:(?:[0-9]+\.?[0-9]*|\.[0-9]+)(?:[eE][+-]?[0-9]+)?|(?i:inf|infinity|nan))"
+                }
+                "bool" => "true|false",
+                _ => "(?s:.)",
+            },
+        }
+    }
+
+    /// Whether a path segment can satisfy both constraints.
+    /// The constraints are compiled to automata, which are walked together until
+    /// both accept the same segment or no segment is left to try.
+    /// A regular expression that can't be compiled is assumed to overlap with everything.
+    pub fn overlaps(&self, other: &Constraint) -> bool {
+        if self == other {
+            return true;
+        }
+        let build = |c: &Constraint| dense::DFA::new(&format!("^(?:{})$", c.pattern())).ok();
+        let (a, b) = match (build(self), build(other)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return true,
+        };
+        let config = start::Config::new().anchored(Anchored::Yes);
+        let (a_start, b_start) = match (a.start_state(&config), b.start_state(&config)) {
+            (Ok(a), Ok(b)) => (a, b),
+            _ => return true,
+        };
+
+        let mut seen = HashSet::new();
+        let mut states = vec![(a_start, b_start, false)];
+        while let Some((sa, sb, consumed)) = states.pop() {
+            if !seen.insert((sa, sb, consumed)) || a.is_dead_state(sa) || b.is_dead_state(sb) {
+                continue;
+            }
+            // path segments are never empty
+            if consumed
+                && a.is_match_state(a.next_eoi_state(sa))
+                && b.is_match_state(b.next_eoi_state(sb))
+            {
+                return true;
+            }
+            for byte in 0..=u8::MAX {
+                states.push((a.next_state(sa, byte), b.next_state(sb, byte), true));
+            }
+        }
+        false
+    }
+}
+
+impl<'a> fmt::Display for Constraint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Type(s) | Self::Regex(s) => write!(f, "{}", s),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Route<'a> {
     pub values: Vec<Token<'a>>,
@@ -74,12 +267,32 @@ impl<'a> TryFrom<&'a str> for Route<'a> {
     type Error = String;
 
     fn try_from(s: &'a str) -> Result<Self, Self::Error> {
-        let mut lex = Token::lexer(s);
+        let lex = Token::lexer(s);
         let mut values: Vec<Token<'a>> = vec![];
 
-        while let Some(next) = lex.next() {
+        for next in lex {
+            if let Some(Token::Tail(_)) = values.last() {
+                return Err("tail wildcard must be the last segment".to_string());
+            }
+
             match next {
                 Token::Error => return Err("invalid route".to_string()),
+                Token::Arg(Arg {
+                    name,
+                    constraint: None,
+                }) if name.starts_with('*') => values.push(Token::Tail(&name[1..])),
+                Token::Arg(Arg {
+                    name,
+                    constraint: None,
+                }) if name.ends_with("..") => values.push(Token::Tail(&name[..name.len() - 2])),
+                Token::Arg(arg) => {
+                    if let Some(re) = arg.constraint.and_then(|c| c.anchored_regex()) {
+                        if let Err(e) = Regex::new(&re) {
+                            return Err(format!("invalid constraint for `{}`: {}", arg.name, e));
+                        }
+                    }
+                    values.push(Token::Arg(arg))
+                }
                 _ => values.push(next),
             }
         }
@@ -88,102 +301,93 @@ impl<'a> TryFrom<&'a str> for Route<'a> {
     }
 }
 
-impl<'a> PartialEq<ReqRoute<'a>> for Route<'a> {
-    fn eq(&self, other: &ReqRoute) -> bool {
-        if self.values.len() != other.values.len() {
-            return false;
-        }
+impl<'a> Route<'a> {
+    fn match_args<'b>(&self, other: &ReqRoute<'b>) -> Option<HashMap<&'a str, &'b str>> {
+        let mut args = HashMap::new();
+        let mut req = other.values.iter().enumerate();
+
+        for tt in self.values.iter() {
+            if let Token::Tail(key) = tt {
+                let rest = match req.next() {
+                    Some((i, _)) => other.tail(i),
+                    None => other.tail(other.values.len()),
+                };
+                args.insert(*key, rest);
+                return Some(args);
+            }
 
-        for (i, tt) in self.values.iter().enumerate() {
-            match (tt, &other.values[i]) {
+            let (_, rt) = req.next()?;
+            match (tt, rt) {
+                (Token::Slash, ReqToken::Slash) | (Token::Wildcard, ReqToken::PathSegment(_)) => {}
                 (Token::PathSegment(left), ReqToken::PathSegment(right)) => {
                     if left != right {
-                        return false;
+                        return None;
+                    }
+                }
+                (Token::Arg(arg), ReqToken::PathSegment(value)) => {
+                    if !arg.is_match(value) {
+                        return None;
                     }
+                    args.insert(arg.name, *value);
                 }
-                _ => {}
+                _ => return None,
             }
         }
 
-        true
+        if req.next().is_some() {
+            return None;
+        }
+        Some(args)
+    }
+}
+
+impl<'a> PartialEq<ReqRoute<'a>> for Route<'a> {
+    fn eq(&self, other: &ReqRoute) -> bool {
+        self.match_args(other).is_some()
     }
 }
 
 impl<'a> ReqRoute<'a> {
     pub fn extract_args(&self, route: &Route<'a>) -> Result<HashMap<&'a str, &'a str>, String> {
-        if route != self {
-            return Err("routes are not matching".to_string());
-        }
-
-        let mut args = HashMap::new();
-        for (i, tok) in route.values.iter().enumerate() {
-            match (tok, &self.values[i]) {
-                (Token::Arg(key), ReqToken::PathSegment(value)) => {
-                    args.insert(&key[1..key.len() - 1], *value);
-                }
-                _ => {}
-            }
-        }
-        Ok(args)
+        route
+            .match_args(self)
+            .ok_or_else(|| "routes are not matching".to_string())
     }
 }
 
 #[test]
 fn route_to_string() {
     let def_route = Route::try_from("/user/id/{article}").unwrap();
-    //let def_route = Route::try_from("/user/{id}/{article}").unwrap();
     let req_route = ReqRoute::try_from("/user/id/1").unwrap();
     assert_eq!(def_route, req_route);
     let args = req_route.extract_args(&def_route).unwrap();
-    panic!("{:#?}", args);
-
-    // asd("/user/{name}");
-    // asd("/user/article");
-    // let mut v = vec![
-    //     "/user/{name}",
-    //     "/user/article",
-    //     "user/{id}/article",
-    //     "user/id/article",
-    //     "user/id/{article}",
-    // ];
-    // v.sort_by(|left, right| {
-    //     let left_matches: Vec<usize> = left.match_indices('{').map(|t| t.0).collect();
-    //     let left_count = left_matches.iter().fold(0, |acc, a| acc + a);
-    //
-    //     if left_matches.len() == 0 {
-    //         return Ordering::Less;
-    //     }
-    //
-    //     let right_matches: Vec<usize> = right.match_indices('{').map(|t| t.0).collect();
-    //     let right_count = right_matches.iter().fold(0, |acc, a| acc + a);
-    //
-    //     if right_matches.len() == 0 {
-    //         return Ordering::Greater;
-    //     }
-    //
-    //     if left_matches.len() + left_count > right_matches.len() + right_count {
-    //         return Ordering::Less;
-    //     }
-    //
-    //     Ordering::Greater
-    // });
-    //println!("{:#?}", v);
-}
-
-impl<'a> ToString for Route<'a> {
-    fn to_string(&self) -> String {
-        let mut s = String::new();
+    assert_eq!(args.len(), 1);
+    assert_eq!(args.get("article"), Some(&"1"));
+    assert_eq!(def_route.to_string(), "/user/id/{article}");
+
+    let def_route = Route::try_from("/post/{id:u64}/{*rest}").unwrap();
+    assert_eq!(def_route.to_string(), "/post/{id:u64}/{*rest}");
+}
 
+impl<'a> fmt::Display for Route<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for tt in &self.values {
-            let fragment = match tt {
-                Token::Arg(s) => s,
-                Token::PathSegment(s) => s,
-                Token::Slash => "/",
-                _ => "",
-            };
-            s.push_str(fragment);
+            match tt {
+                Token::Arg(arg) => {
+                    write!(f, "{{{}", arg.name)?;
+                    if let Some(c) = &arg.constraint {
+                        write!(f, ":{}", c)?;
+                    }
+                    f.write_str("}")?;
+                }
+                Token::PathSegment(seg) => f.write_str(seg)?,
+                Token::Slash => f.write_str("/")?,
+                Token::Wildcard => f.write_str("*")?,
+                Token::Tail(name) => write!(f, "{{*{}}}", name)?,
+                Token::Error => {}
+            }
         }
-        s
+        Ok(())
     }
 }
 
@@ -225,3 +429,61 @@ fn are_not_equal() {
     let args = right.extract_args(&left);
     assert_eq!(args, Err("routes are not matching".to_string()));
 }
+
+#[test]
+fn wildcards() {
+    let left: Route = Route::try_from("/static/{*path}").unwrap();
+    let right: ReqRoute = ReqRoute::try_from("/static/css/main.css").unwrap();
+    assert_eq!(left, right);
+
+    let args = right.extract_args(&left).unwrap();
+    assert_eq!(args.get("path"), Some(&"css/main.css"));
+
+    let right: ReqRoute = ReqRoute::try_from("/static/").unwrap();
+    let args = right.extract_args(&left).unwrap();
+    assert_eq!(args.get("path"), Some(&""));
+
+    let right: ReqRoute = ReqRoute::try_from("/static").unwrap();
+    assert_ne!(left, right);
+
+    let left: Route = Route::try_from("/files/{rest..}").unwrap();
+    let right: ReqRoute = ReqRoute::try_from("/files/a/b/c").unwrap();
+    let args = right.extract_args(&left).unwrap();
+    assert_eq!(args.get("rest"), Some(&"a/b/c"));
+    assert_eq!(left.to_string(), "/files/{*rest}");
+
+    let left: Route = Route::try_from("/user/*/article/{article}").unwrap();
+    let right: ReqRoute = ReqRoute::try_from("/user/1/article/2").unwrap();
+    let args = right.extract_args(&left).unwrap();
+    assert_eq!(args.len(), 1);
+    assert_eq!(args.get("article"), Some(&"2"));
+
+    let right: ReqRoute = ReqRoute::try_from("/user/1/2/article/2").unwrap();
+    assert_ne!(left, right);
+
+    assert!(Route::try_from("/static/{*path}/more").is_err());
+}
+
+#[test]
+fn constraints() {
+    let left: Route = Route::try_from("/user/{id:u64}").unwrap();
+    let right: ReqRoute = ReqRoute::try_from("/user/12").unwrap();
+    assert_eq!(left, right);
+    assert_eq!(right.extract_args(&left).unwrap().get("id"), Some(&"12"));
+
+    let right: ReqRoute = ReqRoute::try_from("/user/petar").unwrap();
+    assert_ne!(left, right);
+
+    let left: Route = Route::try_from("/post/{slug:[a-z0-9-]+}").unwrap();
+    let right: ReqRoute = ReqRoute::try_from("/post/hello-world-2").unwrap();
+    assert_eq!(
+        right.extract_args(&left).unwrap().get("slug"),
+        Some(&"hello-world-2")
+    );
+    assert_eq!(left.to_string(), "/post/{slug:[a-z0-9-]+}");
+
+    let right: ReqRoute = ReqRoute::try_from("/post/Hello").unwrap();
+    assert_ne!(left, right);
+
+    assert!(Route::try_from("/post/{slug:[a-z}").is_err());
+}
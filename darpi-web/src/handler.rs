@@ -1,3 +1,4 @@
+use crate::timeout::Timeout;
 use crate::{Body, Response};
 use async_trait::async_trait;
 use http::request::Parts as RequestParts;
@@ -19,6 +20,11 @@ where
     C: 'static + Sync + Send,
 {
     async fn call(&self, args: Args<'a, C>) -> Result<Response<Body>, Infallible>;
+
+    /// Replaces the timeout of the app for this handler
+    fn timeout(&self) -> Option<Timeout> {
+        None
+    }
 }
 
 #[async_trait]
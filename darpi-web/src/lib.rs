@@ -10,5 +10,7 @@ pub mod logger;
 pub mod middleware;
 pub mod request;
 pub mod response;
+pub mod routes;
+pub mod timeout;
 pub mod xml;
 pub mod yaml;
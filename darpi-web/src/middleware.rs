@@ -3,6 +3,14 @@ use async_trait::async_trait;
 use http::request::Parts as RequestParts;
 use std::sync::Arc;
 
+/// The types of a middleware, whatever the container it is given.
+/// `routes!` bounds its middleware with them, so they stay known.
+pub trait MiddlewareTypes {
+    type HandlerArgs;
+    type Error;
+    type Type;
+}
+
 #[async_trait]
 pub trait RequestMiddleware<M>
 where
@@ -7,6 +7,7 @@ use hyper::Body;
 use hyper::Response;
 use serde::de;
 use serde_urlencoded;
+use std::fmt;
 use std::sync::Arc;
 
 #[async_trait]
@@ -137,3 +138,67 @@ where
         }
     }
 }
+
+/// A typed header, extracted by `#[header("name")] value: T` in handlers.
+/// With `Option<T>`, a missing header is `None` instead of an error.
+pub trait FromHeader: Sized {
+    type Error: fmt::Display;
+
+    fn from_header(value: &HeaderValue) -> Result<Self, Self::Error>;
+}
+
+/// A typed header with a well known name, which `#[header] value: T` extracts without one
+pub trait NamedHeader: FromHeader {
+    const NAME: &'static str;
+}
+
+impl FromHeader for String {
+    type Error = http::header::ToStrError;
+
+    fn from_header(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value.to_str().map(ToString::to_string)
+    }
+}
+
+impl FromHeader for HeaderValue {
+    type Error = std::convert::Infallible;
+
+    fn from_header(value: &HeaderValue) -> Result<Self, Self::Error> {
+        Ok(value.clone())
+    }
+}
+
+/// A header which is missing or can't be parsed
+#[derive(Debug, Display)]
+pub enum HeaderError {
+    #[display(fmt = "missing header `{}`", _0)]
+    Missing(&'static str),
+    #[display(fmt = "malformed header `{}`: {}", _0, _1)]
+    Malformed(&'static str, String),
+}
+
+impl ResponderError for HeaderError {
+    fn status_code(&self) -> http::StatusCode {
+        http::StatusCode::BAD_REQUEST
+    }
+}
+impl std::error::Error for HeaderError {}
+
+// on the error itself, since a blanket impl over every `FromHeader` would
+// keep other crates from implementing `ErrResponder<HeaderError, _>` for their types
+impl response::ErrResponder<HeaderError, Body> for HeaderError {
+    fn respond_err(e: HeaderError) -> Response<Body> {
+        ResponderError::respond_err(&e)
+    }
+}
+
+/// The header `name` as a `T`, or `None` if it is missing
+pub fn header<T: FromHeader>(
+    headers: &HeaderMap,
+    name: &'static str,
+) -> Result<Option<T>, HeaderError> {
+    headers
+        .get(name)
+        .map(|value| T::from_header(value).map_err(|e| HeaderError::Malformed(name, e.to_string())))
+        .transpose()
+}
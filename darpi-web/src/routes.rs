@@ -0,0 +1,24 @@
+use crate::{Body, Response};
+use async_trait::async_trait;
+use http::request::Parts as RequestParts;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+/// A set of routes, generated by `routes!`, that can be mounted under a prefix with `app!`.
+/// It is implemented for every container the handlers, middleware and jobs can work with,
+/// so `app!` fails to compile if its container doesn't satisfy them.
+#[async_trait]
+pub trait Routes<C>
+where
+    C: 'static + Sync + Send,
+{
+    /// `route` is the request path without the mount prefix.
+    /// The body is given back if none of the routes matched the path.
+    async fn call(
+        &self,
+        route: &str,
+        request_parts: &mut RequestParts,
+        container: Arc<C>,
+        body: Body,
+    ) -> Result<Result<Response<Body>, Infallible>, Body>;
+}
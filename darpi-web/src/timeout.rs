@@ -0,0 +1,136 @@
+use crate::handler::{Args, Handler};
+use crate::response::ResponderError;
+use crate::{Body, Request, Response, StatusCode};
+use http::request::Parts as RequestParts;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long a request may take, set with the `timeout` key of `app!` or `#[handler]`.
+/// A `Duration` converts into a timeout answered with `503 Service Unavailable`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeout {
+    after: Duration,
+    status: StatusCode,
+}
+
+impl Timeout {
+    pub fn new(after: Duration) -> Self {
+        Self {
+            after,
+            status: StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// The status of the response, usually `503` or `504`
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn after(&self) -> Duration {
+        self.after
+    }
+}
+
+impl From<Duration> for Timeout {
+    fn from(after: Duration) -> Self {
+        Self::new(after)
+    }
+}
+
+/// The error of a request that took too long.
+/// It is also stored in the extensions of its response, for the response middleware.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimedOut(Timeout);
+
+impl TimedOut {
+    pub fn after(&self) -> Duration {
+        self.0.after
+    }
+
+    /// The response to the request, with `self` in its extensions
+    pub fn response(&self) -> Response<Body> {
+        let mut rb = self.respond_err();
+        rb.extensions_mut().insert(*self);
+        rb
+    }
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request timed out after {}ms", self.0.after.as_millis())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl ResponderError for TimedOut {
+    fn status_code(&self) -> StatusCode {
+        self.0.status
+    }
+}
+
+/// When the request started and the timeout of the app, stored in the request extensions
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    start: Instant,
+    timeout: Option<Timeout>,
+}
+
+impl Timer {
+    /// Starts timing the request
+    pub fn start(timeout: Option<Timeout>, req: &mut Request<Body>) {
+        req.extensions_mut().insert(Self {
+            start: Instant::now(),
+            timeout,
+        });
+    }
+
+    /// The timer of the request, which never expires if it was not started
+    pub fn from_parts(parts: &RequestParts) -> Self {
+        match parts.extensions.get::<Self>() {
+            Some(timer) => *timer,
+            None => Self {
+                start: Instant::now(),
+                timeout: None,
+            },
+        }
+    }
+
+    /// Runs `fut` until the timeout of the app expires
+    pub async fn within<F: Future>(&self, fut: F) -> Result<F::Output, TimedOut> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout_at(self.start + timeout.after, fut)
+                .await
+                .map_err(|_| TimedOut(timeout)),
+            None => Ok(fut.await),
+        }
+    }
+
+    /// Calls `handler`, cancelling it once the timeout of the app expires.
+    /// The timeout of the handler replaces the one of the app and starts with the call.
+    pub async fn call<'a, C, H>(
+        &self,
+        handler: &H,
+        args: Args<'a, C>,
+    ) -> Result<Response<Body>, Infallible>
+    where
+        C: 'static + Sync + Send,
+        H: Handler<'a, C> + ?Sized,
+    {
+        let res = match handler.timeout() {
+            Some(timeout) => {
+                let timer = Self {
+                    start: Instant::now(),
+                    timeout: Some(timeout),
+                };
+                timer.within(handler.call(args)).await
+            }
+            None => self.within(handler.call(args)).await,
+        };
+        res.unwrap_or_else(|timed_out| Ok(timed_out.response()))
+    }
+}
@@ -0,0 +1,227 @@
+//! Layered configuration, so deployments differ only by their config.
+//! Files are read in the order they are added, each one overriding the keys of the ones before,
+//! and the environment variables of the prefix override them all.
+//!
+//! ```no_run
+//! use darpi::config::{Config, Settings};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct AppConfig {
+//!     address: String,
+//!     workers: usize,
+//! }
+//!
+//! impl Settings for AppConfig {
+//!     fn validate(&self) -> Result<(), String> {
+//!         match self.workers {
+//!             0 => Err("`workers` can't be 0".to_string()),
+//!             _ => Ok(()),
+//!         }
+//!     }
+//! }
+//!
+//! let config: AppConfig = Config::new()
+//!     .file("config/default.toml")
+//!     .optional_file("config/production.yaml")
+//!     .load()
+//!     .unwrap_or_else(|e| panic!("{}", e));
+//! ```
+//!
+//! With the default prefix, `DARPI_WORKERS=8` overrides `workers` and `DARPI_DB__URL`
+//! overrides `url` of the `db` table, as `__` separates the keys of nested tables.
+//! Values overriding a string of the files stay strings, so `DARPI_NAME=1234` is the name `"1234"`.
+//! Other values are read as JSON when they are valid JSON, like `DARPI_WORKERS=8`
+//! or `DARPI_HOSTS='["a", "b"]'`, and as strings otherwise.
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A configuration, which is deserialized and validated by `Config::load`
+pub trait Settings: DeserializeOwned {
+    /// Checks the values which can't be expressed by their types. By default, they are all valid.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The sources of a configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    files: Vec<(PathBuf, bool)>,
+    env_prefix: Option<String>,
+    env: Option<Vec<(String, String)>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// No files and the environment variables prefixed with `DARPI_`
+    pub fn new() -> Self {
+        Self {
+            files: vec![],
+            env_prefix: Some("DARPI".to_string()),
+            env: None,
+        }
+    }
+
+    /// A `.toml`, `.yaml`, `.yml` or `.json` file, which has to exist
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push((path.as_ref().to_path_buf(), true));
+        self
+    }
+
+    /// A file which is skipped if it doesn't exist, like the one of an environment
+    pub fn optional_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push((path.as_ref().to_path_buf(), false));
+        self
+    }
+
+    /// The prefix of the environment variables, `None` to ignore them
+    pub fn env_prefix(mut self, prefix: Option<&str>) -> Self {
+        self.env_prefix = prefix.map(ToString::to_string);
+        self
+    }
+
+    /// The variables read instead of the environment of the process, like in tests
+    pub fn env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env = Some(
+            vars.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Reads the sources and validates the result
+    pub fn load<T: Settings>(&self) -> Result<T, ConfigError> {
+        let mut value = Value::Object(Map::new());
+        for (path, required) in &self.files {
+            if !required && !path.exists() {
+                continue;
+            }
+            merge(&mut value, read_file(path)?);
+        }
+        if let Some(prefix) = &self.env_prefix {
+            let env = match &self.env {
+                Some(vars) => read_env(prefix, vars.iter().cloned(), &value),
+                None => read_env(prefix, std::env::vars(), &value),
+            };
+            merge(&mut value, env);
+        }
+
+        let settings = T::deserialize(value).map_err(ConfigError::Deserialize)?;
+        settings.validate().map_err(ConfigError::Invalid)?;
+        Ok(settings)
+    }
+}
+
+fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    let parse_error = |e: &dyn fmt::Display| ConfigError::Parse(path.to_path_buf(), e.to_string());
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(|e| parse_error(&e)),
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|e| parse_error(&e)),
+        Some("json") => serde_json::from_str(&content).map_err(|e| parse_error(&e)),
+        _ => Err(ConfigError::Format(path.to_path_buf())),
+    }
+}
+
+/// The variables of `prefix`, typed like the values of `base` they override
+fn read_env(prefix: &str, vars: impl Iterator<Item = (String, String)>, base: &Value) -> Value {
+    let prefix = format!("{}_", prefix);
+    let mut value = Value::Object(Map::new());
+
+    for (key, raw) in vars {
+        let key = match key.strip_prefix(&prefix) {
+            Some(key) if !key.is_empty() => key.to_lowercase(),
+            _ => continue,
+        };
+        let keys: Vec<&str> = key.split("__").collect();
+        let existing = keys.iter().try_fold(base, |value, key| value.get(key));
+        let raw = match existing {
+            Some(Value::String(_)) => Value::String(raw),
+            _ => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+        };
+        merge(&mut value, nest(&keys, raw));
+    }
+    value
+}
+
+/// `raw` in the tables of `keys`, like `{"db": {"url": raw}}` for `db__url`
+fn nest(keys: &[&str], raw: Value) -> Value {
+    match keys.split_first() {
+        Some((key, rest)) => {
+            let mut table = Map::new();
+            table.insert(key.to_string(), nest(rest, raw));
+            Value::Object(table)
+        }
+        None => raw,
+    }
+}
+
+/// Overrides the keys of `base` with the ones of `layer`, merging tables
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Why a configuration couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    /// The file isn't `.toml`, `.yaml`, `.yml` or `.json`
+    Format(PathBuf),
+    Deserialize(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, e) => write!(f, "could not read `{}`: {}", path.display(), e),
+            Self::Parse(path, e) => write!(f, "could not parse `{}`: {}", path.display(), e),
+            Self::Format(path) => write!(
+                f,
+                "unknown config format of `{}`, expected toml, yaml or json",
+                path.display()
+            ),
+            Self::Deserialize(e) => write!(f, "invalid config: {}", e),
+            Self::Invalid(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read(_, e) => Some(e),
+            Self::Deserialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
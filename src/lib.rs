@@ -1,12 +1,25 @@
 #![forbid(unsafe_code)]
 
 pub use darpi_code_gen::{
-    app, from_path, handler, job_factory, middleware, req_formatter, resp_formatter, Query,
+    app, from_path, handler, job_factory, middleware, req_formatter, resp_formatter, routes, Query,
 };
+pub mod config;
+pub mod listen;
+pub mod panic;
+pub mod pool;
+pub mod scope;
+pub mod server;
+pub mod shutdown;
+pub mod state;
+pub mod test;
+#[cfg(feature = "tls")]
+pub mod tls;
+
 pub use darpi_web::{
     handler::Args, handler::Handler, job, job::RequestJobFactory, job::ResponseJobFactory, logger,
-    logger::ReqFormatter, logger::RespFormatter, middleware::RequestMiddleware,
-    middleware::ResponseMiddleware, request, response, xml::Xml, yaml::Yaml, Json,
+    logger::ReqFormatter, logger::RespFormatter, middleware::MiddlewareTypes,
+    middleware::RequestMiddleware, middleware::ResponseMiddleware, request, response,
+    routes::Routes, timeout, xml::Xml, yaml::Yaml, Json,
 };
 
 use crate::job::Job;
@@ -17,7 +30,9 @@ pub use futures;
 pub use http::{header, request::Parts as RequestParts, Method, StatusCode};
 pub use hyper::{self, body, body::HttpBody, service, Body, Error, Request, Response, Server};
 pub use log;
+pub use once_cell;
 pub use rayon;
+pub use regex;
 use serde::{de, Deserialize, Deserializer};
 pub use serde_json;
 use std::fmt::Display;
@@ -53,7 +68,7 @@ where
         }
         Job::CpuBound(cpu) => {
             let (otx, recv) = oneshot::channel();
-            rayon::spawn(move || {
+            pool::CpuPool::current().spawn(move || {
                 let _ = otx.send(cpu.into_inner()());
             });
             Ok(recv)
@@ -69,21 +84,30 @@ where
     }
 }
 
+/// Runs the job in the background.
+/// The graceful shutdown of the server handling the request waits for it to finish.
+/// A `CpuJob` runs on the cpu pool of the app handling the request.
 pub async fn spawn<T>(job: impl Into<Job<T>>) -> Result<(), SendError<Job<T>>>
 where
     T: Send + 'static,
 {
     let job = job.into();
+    let guard = shutdown::track();
     match job {
         Job::Future(fut) => {
             let handle = tokio::runtime::Handle::current();
-            handle.spawn(async {
-                fut.into_inner().await;
+            handle.spawn(async move {
+                match guard {
+                    // the jobs it spawns are waited for too
+                    Some(guard) => guard.scope(fut.into_inner()).await,
+                    None => fut.into_inner().await,
+                }
             });
             Ok(())
         }
         Job::CpuBound(cpu) => {
-            rayon::spawn(move || {
+            pool::CpuPool::current().spawn(move || {
+                let _guard = guard;
                 cpu.into_inner()();
             });
             Ok(())
@@ -91,6 +115,7 @@ where
         Job::IOBlocking(io_blocking) => {
             let handle = tokio::runtime::Handle::current();
             handle.spawn_blocking(move || {
+                let _guard = guard;
                 io_blocking.into_inner()();
             });
             Ok(())
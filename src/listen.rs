@@ -0,0 +1,302 @@
+//! The sockets an `App` listens on.
+//! Besides tcp addresses, an `App` can listen on unix domain sockets and on sockets
+//! inherited from its parent process, like the ones passed by systemd socket activation.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Where to listen, parsed from the `address` of `app!` or `App::listen`:
+///
+/// - `127.0.0.1:3000` a tcp address
+/// - `unix:/run/app.sock` a unix domain socket, replacing a stale socket file at the path
+/// - `systemd` all sockets passed with `LISTEN_FDS` by systemd socket activation,
+///   which `receive_systemd_sockets` has to receive first
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    #[cfg(unix)]
+    Systemd,
+}
+
+#[derive(Debug)]
+pub struct InvalidAddress(String);
+
+impl fmt::Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid server address: `{}`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidAddress {}
+
+impl FromStr for Address {
+    type Err = InvalidAddress;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidAddress(s.to_string());
+
+        #[cfg(unix)]
+        {
+            if s == "systemd" {
+                return Ok(Address::Systemd);
+            }
+            if let Some(path) = s.strip_prefix("unix:") {
+                if path.is_empty() {
+                    return Err(invalid());
+                }
+                return Ok(Address::Unix(PathBuf::from(path)));
+            }
+        }
+
+        s.parse().map(Address::Tcp).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Address::Systemd => write!(f, "systemd"),
+        }
+    }
+}
+
+impl Address {
+    /// Binds or adopts the sockets of the address
+    pub(crate) fn bind(&self) -> io::Result<Vec<Socket>> {
+        match self {
+            Address::Tcp(addr) => {
+                let listener = std::net::TcpListener::bind(addr)?;
+                Ok(vec![Socket::tcp(listener)?])
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                // a socket left by a server which is gone is replaced, but not a live one
+                if let Ok(meta) = std::fs::metadata(path) {
+                    if meta.file_type().is_socket() {
+                        match std::os::unix::net::UnixStream::connect(path) {
+                            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                                std::fs::remove_file(path)?
+                            }
+                            _ => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::AddrInUse,
+                                    "another server is listening on the socket",
+                                ))
+                            }
+                        }
+                    }
+                }
+                let listener = std::os::unix::net::UnixListener::bind(path)?;
+                Ok(vec![Socket::unix(listener)?])
+            }
+            #[cfg(unix)]
+            Address::Systemd => systemd_sockets(),
+        }
+    }
+}
+
+/// The sockets passed by systemd socket activation, once they are received
+#[cfg(unix)]
+static SYSTEMD: once_cell::sync::Lazy<std::sync::Mutex<Option<listenfd::ListenFd>>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Receives the sockets passed with `LISTEN_FDS` by systemd socket activation,
+/// which the `systemd` address listens on.
+///
+/// The `LISTEN_*` variables are removed, so the sockets are neither adopted twice nor by child
+/// processes. Changing the environment races with any other thread reading it, so this has to be
+/// called at the start of `main`, before a runtime or any other thread is started.
+/// It fails when called within a runtime.
+#[cfg(unix)]
+pub fn receive_systemd_sockets() -> io::Result<()> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(io::Error::other(
+            "the sockets of `LISTEN_FDS` have to be received before the runtime is started",
+        ));
+    }
+
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = listenfd::ListenFd::from_env();
+    *SYSTEMD.lock().unwrap_or_else(|e| e.into_inner()) = Some(fds);
+
+    match pid {
+        Some(pid) if pid.parse() != Ok(std::process::id()) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the sockets of `LISTEN_FDS` were passed to another process",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Takes the received sockets, which are either tcp or unix ones.
+/// A passed file descriptor which is neither is an error and stays open.
+#[cfg(unix)]
+fn systemd_sockets() -> io::Result<Vec<Socket>> {
+    let mut guard = SYSTEMD.lock().unwrap_or_else(|e| e.into_inner());
+    let fds = guard.as_mut().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "the sockets of `LISTEN_FDS` were not received, \
+             call `darpi::listen::receive_systemd_sockets` at the start of `main`",
+        )
+    })?;
+    if fds.len() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no sockets were passed with `LISTEN_FDS`",
+        ));
+    }
+
+    let mut sockets = vec![];
+    for idx in 0..fds.len() {
+        if let Ok(Some(listener)) = fds.take_tcp_listener(idx) {
+            sockets.push(Socket::tcp(listener)?);
+            continue;
+        }
+        // `None` when another `systemd` address already listens on it
+        if let Some(listener) = fds.take_unix_listener(idx)? {
+            sockets.push(Socket::unix(listener)?);
+        }
+    }
+    if sockets.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the sockets of `LISTEN_FDS` are already listened on",
+        ));
+    }
+    Ok(sockets)
+}
+
+/// A listening socket
+pub(crate) enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Socket {
+    fn tcp(listener: std::net::TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        TcpListener::from_std(listener).map(Socket::Tcp)
+    }
+
+    #[cfg(unix)]
+    fn unix(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
+        let path = listener
+            .local_addr()?
+            .as_pathname()
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        listener.set_nonblocking(true)?;
+        UnixListener::from_std(listener).map(|l| Socket::Unix(l, path))
+    }
+
+    /// The address of the bound socket, with the port picked by the os
+    pub(crate) fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Socket::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    pub(crate) fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Stream>> {
+        match self {
+            Socket::Tcp(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, addr)| Stream::Tcp(stream, addr)),
+            #[cfg(unix)]
+            Socket::Unix(listener, _) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+/// An accepted connection
+pub enum Stream {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// The address of the client, which is only known for tcp connections
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(_, addr) => Some(*addr),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+
+    pub(crate) fn set_nodelay(&self, enabled: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream, _) => stream.set_nodelay(enabled),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream, _) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream, _) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream, _) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream, _) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Panics in handlers and middleware are caught per request, instead of aborting the connection.
+//! They are reported to a `PanicHandler`, which also builds the response.
+
+use futures::FutureExt;
+use http::{Method, StatusCode};
+use hyper::{Body, Response};
+use std::any::Any;
+use std::convert::Infallible;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+/// The request that panicked
+pub struct PanicInfo<'a> {
+    method: &'a Method,
+    route: &'a str,
+    message: &'a str,
+}
+
+impl<'a> PanicInfo<'a> {
+    pub fn method(&self) -> &Method {
+        self.method
+    }
+
+    pub fn route(&self) -> &str {
+        self.route
+    }
+
+    /// The panic message, if it was a string
+    pub fn message(&self) -> &str {
+        self.message
+    }
+}
+
+/// Reports panics and builds the response for the request.
+/// The process-wide panic hook still runs before it.
+pub trait PanicHandler: Send + Sync {
+    fn report(&self, info: &PanicInfo<'_>) {
+        log::error!(
+            "panic in {} {} reason: `{}`",
+            info.method(),
+            info.route(),
+            info.message()
+        );
+    }
+
+    fn respond(&self, _info: &PanicInfo<'_>) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .expect("this cannot happen")
+    }
+}
+
+/// Logs the panic and responds with an empty `500 Internal Server Error`
+pub struct DefaultPanicHandler;
+
+impl PanicHandler for DefaultPanicHandler {}
+
+/// The `PanicHandler` of the app, stored in the request extensions
+#[derive(Clone)]
+struct Hook(Arc<dyn PanicHandler>);
+
+/// Catches panics of the futures of a request
+pub struct Catch {
+    handler: Arc<dyn PanicHandler>,
+    method: Method,
+    route: String,
+}
+
+impl Catch {
+    /// Catches the panics of the request, which is handled by `handler`.
+    /// It is made available to `Catch::from_parts` through the request extensions.
+    pub fn new(handler: Arc<dyn PanicHandler>, req: &mut http::Request<Body>) -> Self {
+        req.extensions_mut().insert(Hook(Arc::clone(&handler)));
+        Self {
+            handler,
+            method: req.method().clone(),
+            route: req.uri().path().to_string(),
+        }
+    }
+
+    pub fn from_parts(parts: &http::request::Parts) -> Self {
+        let handler = match parts.extensions.get::<Hook>() {
+            Some(hook) => Arc::clone(&hook.0),
+            None => Arc::new(DefaultPanicHandler),
+        };
+        Self {
+            handler,
+            method: parts.method.clone(),
+            route: parts.uri.path().to_string(),
+        }
+    }
+
+    /// Runs `fut`, answering with the response of the `PanicHandler` if it panics
+    pub async fn run<F>(self, fut: F) -> Result<Response<Body>, Infallible>
+    where
+        F: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        match AssertUnwindSafe(fut).catch_unwind().await {
+            Ok(res) => res,
+            Err(panic) => {
+                let info = PanicInfo {
+                    method: &self.method,
+                    route: &self.route,
+                    message: message(&*panic),
+                };
+                self.handler.report(&info);
+                Ok(self.handler.respond(&info))
+            }
+        }
+    }
+}
+
+pub(crate) fn message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&'static str>() {
+        Some(s) => s,
+        None => match panic.downcast_ref::<String>() {
+            Some(s) => &s[..],
+            None => "Unknown",
+        },
+    }
+}
@@ -0,0 +1,103 @@
+//! The thread pool running `CpuJob`s.
+//! Each `App` owns one, configured with the `cpu_pool` key of `app!`.
+//! Jobs spawned outside of a request run on a default pool shared by the process.
+
+use once_cell::sync::Lazy;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::future::Future;
+use std::sync::Arc;
+
+tokio::task_local! {
+    static CURRENT: CpuPool;
+}
+
+static DEFAULT: Lazy<CpuPool> = Lazy::new(|| {
+    CpuPool::builder()
+        .thread_name("darpi-cpu")
+        .build()
+        .expect("could not build the default cpu pool")
+});
+
+#[derive(Clone)]
+pub struct CpuPool(Arc<ThreadPool>);
+
+impl CpuPool {
+    pub fn builder() -> CpuPoolBuilder {
+        CpuPoolBuilder::default()
+    }
+
+    /// The pool shared by everything without its own pool
+    pub fn default_pool() -> Self {
+        DEFAULT.clone()
+    }
+
+    /// The pool of the request being handled or the default one
+    pub fn current() -> Self {
+        CURRENT
+            .try_with(|pool| pool.clone())
+            .unwrap_or_else(|_| Self::default_pool())
+    }
+
+    /// Runs `f` with this pool as the current one
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.0.spawn(f)
+    }
+
+    pub fn current_num_threads(&self) -> usize {
+        self.0.current_num_threads()
+    }
+}
+
+/// Configures a `CpuPool`.
+/// Unset options keep the defaults of rayon, which uses one thread per cpu.
+#[derive(Default)]
+pub struct CpuPoolBuilder {
+    threads: Option<usize>,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+}
+
+impl CpuPoolBuilder {
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Threads are named `{name}-{index}`
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    /// The stack size of the threads in bytes
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    pub fn build(self) -> Result<CpuPool, ThreadPoolBuildError> {
+        let mut builder = ThreadPoolBuilder::new().panic_handler(|panic| {
+            log::warn!(
+                "panic in cpu job reason: `{}`",
+                crate::panic::message(&*panic)
+            );
+        });
+        if let Some(threads) = self.threads {
+            builder = builder.num_threads(threads);
+        }
+        if let Some(name) = self.thread_name {
+            builder = builder.thread_name(move |i| format!("{}-{}", name, i));
+        }
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        builder.build().map(|pool| CpuPool(Arc::new(pool)))
+    }
+}
@@ -0,0 +1,139 @@
+//! Values living as long as a request, like a database transaction or the current user.
+//! They are built on their first `#[inject]` in a handler or request middleware and shared
+//! by the rest of the request. Once the response is produced, they are finished with it.
+
+use crate::response::ResponderError;
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture};
+use http::request::Parts as RequestParts;
+use hyper::{Body, Request, Response};
+use std::any::{Any, TypeId};
+use std::convert::Infallible;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/// A value built once per request, which handlers and request middleware receive
+/// with `#[inject] value: Scoped<T>`
+#[async_trait]
+pub trait RequestScoped<C>: Sized + Send + Sync + 'static
+where
+    C: 'static + Sync + Send,
+{
+    type Error: ResponderError;
+
+    async fn provide(parts: &RequestParts, container: Arc<C>) -> Result<Self, Self::Error>;
+
+    /// Called with the response, to commit or roll back. By default, the value is dropped
+    async fn finish(self, _response: &Response<Body>) {}
+}
+
+type Value = Arc<dyn Any + Send + Sync>;
+
+struct Entry {
+    type_id: TypeId,
+    value: Value,
+    finish: for<'r> fn(Value, &'r Response<Body>) -> BoxFuture<'r, ()>,
+}
+
+fn finish<C, T>(value: Value, response: &Response<Body>) -> BoxFuture<'_, ()>
+where
+    C: 'static + Sync + Send,
+    T: RequestScoped<C>,
+{
+    match value.downcast::<T>().map(Arc::try_unwrap) {
+        Ok(Ok(value)) => value.finish(response),
+        _ => {
+            log::warn!(
+                "`{}` outlived its request and is not finished",
+                std::any::type_name::<T>()
+            );
+            Box::pin(future::ready(()))
+        }
+    }
+}
+
+/// The request scoped values of a request, stored in its extensions
+#[derive(Clone, Default)]
+pub struct Scope(Arc<Mutex<Vec<Entry>>>);
+
+impl Scope {
+    pub fn new(req: &mut Request<Body>) -> Self {
+        let scope = Self::default();
+        req.extensions_mut().insert(scope.clone());
+        scope
+    }
+
+    fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let entries = self.0.lock().expect("poisoned lock");
+        entries
+            .iter()
+            .find(|entry| entry.type_id == TypeId::of::<T>())
+            .and_then(|entry| Arc::clone(&entry.value).downcast::<T>().ok())
+    }
+
+    fn insert<C, T>(&self, value: Arc<T>)
+    where
+        C: 'static + Sync + Send,
+        T: RequestScoped<C>,
+    {
+        self.0.lock().expect("poisoned lock").push(Entry {
+            type_id: TypeId::of::<T>(),
+            value,
+            finish: finish::<C, T>,
+        });
+    }
+
+    /// Runs `fut` and finishes the values with its response, the last built first
+    pub async fn run<F>(self, fut: F) -> Result<Response<Body>, Infallible>
+    where
+        F: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        let res = fut.await;
+        let entries = std::mem::take(&mut *self.0.lock().expect("poisoned lock"));
+        if let Ok(response) = &res {
+            for entry in entries.into_iter().rev() {
+                (entry.finish)(entry.value, response).await;
+            }
+        }
+        res
+    }
+}
+
+/// A request scoped value.
+/// Outside of an app, like in `darpi::test`, it is built on every injection and only dropped.
+pub struct Scoped<T>(Arc<T>);
+
+impl<T: Send + Sync + 'static> Scoped<T> {
+    /// The value of the request, which is built if it is the first one asking for it
+    pub async fn get<C>(parts: &RequestParts, container: Arc<C>) -> Result<Self, T::Error>
+    where
+        C: 'static + Sync + Send,
+        T: RequestScoped<C>,
+    {
+        let scope = parts.extensions.get::<Scope>().cloned();
+        if let Some(value) = scope.as_ref().and_then(Scope::get::<T>) {
+            return Ok(Self(value));
+        }
+
+        let value = Arc::new(T::provide(parts, container).await?);
+        if let Some(scope) = scope {
+            scope.insert::<C, T>(Arc::clone(&value));
+        }
+        Ok(Self(value))
+    }
+}
+
+impl<T> Clone for Scoped<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Deref for Scoped<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,687 @@
+//! The request pipeline of an `app!` and the server running it.
+//! The server supports connection limits and idle and header read timeouts, configured with `ServerConfig`.
+
+use crate::listen::{Address, Socket, Stream};
+use crate::shutdown::Shutdown;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures::task::AtomicWaker;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header;
+use hyper::server::accept::Accept;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Method, Request, Response};
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{delay_until, Delay, Instant};
+
+/// How long accepting connections is paused after an error
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// What HTTP/2 connections without tls start with
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+type Pipeline =
+    dyn Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, Infallible>> + Send + Sync;
+
+/// Routing, middleware, handlers and jobs of an `app!`, as a `hyper` and `tower` service.
+/// It is always ready and cheap to clone.
+#[derive(Clone)]
+pub struct AppService(Arc<Pipeline>);
+
+impl AppService {
+    pub fn new<F>(pipeline: F) -> Self
+    where
+        F: Fn(Request<Body>) -> BoxFuture<'static, Result<Response<Body>, Infallible>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self(Arc::new(pipeline))
+    }
+
+    /// Sends the request through the pipeline.
+    /// Whichever step answered a `HEAD` request, its response is sent without the body.
+    pub fn handle(
+        &self,
+        req: Request<Body>,
+    ) -> BoxFuture<'static, Result<Response<Body>, Infallible>> {
+        if req.method() == Method::HEAD {
+            (self.0)(req).map_ok(without_body).boxed()
+        } else {
+            (self.0)(req)
+        }
+    }
+}
+
+/// The response to a `HEAD` request, with the headers the `GET` would have
+fn without_body(mut res: Response<Body>) -> Response<Body> {
+    let body = std::mem::replace(res.body_mut(), Body::empty());
+    // a length set by the handler is kept
+    if res.headers().contains_key(header::CONTENT_LENGTH) {
+        return res;
+    }
+    match body.size_hint().exact() {
+        Some(len) => {
+            res.headers_mut().insert(header::CONTENT_LENGTH, len.into());
+        }
+        // the length of a streamed body is unknown, so it is not claimed to be `0`
+        None => *res.body_mut() = Body::wrap_stream(futures::stream::empty::<io::Result<Bytes>>()),
+    }
+    res
+}
+
+impl Service<Request<Body>> for AppService {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response<Body>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.handle(req)
+    }
+}
+
+/// Why an `App` could not be run, returned by its `run` and `bind` methods.
+/// `darpi::Error` is still the re-exported `hyper::Error`, which `Serve` wraps.
+#[derive(Debug)]
+pub enum Error {
+    /// Binding a socket or taking a passed one failed
+    Bind(io::Error),
+    /// The server failed while serving connections
+    Serve(hyper::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bind(e) => write!(f, "{}", e),
+            Error::Serve(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Bind(e) => Some(e),
+            Error::Serve(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Bind(e)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Serve(e)
+    }
+}
+
+/// A server that is listening, but serves connections only while awaited or spawned.
+/// Binding to port `0` picks a free port, which `local_addr` returns.
+pub struct Bound {
+    local_addrs: Vec<Address>,
+    server: BoxFuture<'static, Result<(), hyper::Error>>,
+}
+
+impl Bound {
+    pub fn new<F>(local_addrs: Vec<Address>, server: F) -> Self
+    where
+        F: Future<Output = Result<(), hyper::Error>> + Send + 'static,
+    {
+        Self {
+            local_addrs,
+            server: Box::pin(server),
+        }
+    }
+
+    /// The first tcp address the server is listening on
+    ///
+    /// # Panics
+    /// If the server listens only on unix domain sockets
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs
+            .iter()
+            .find_map(|address| match address {
+                Address::Tcp(addr) => Some(*addr),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            })
+            .expect("the server is not listening on a tcp socket")
+    }
+
+    /// All the addresses the server is listening on
+    pub fn local_addrs(&self) -> &[Address] {
+        &self.local_addrs
+    }
+}
+
+impl Future for Bound {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.server.as_mut().poll(cx).map_err(Error::Serve)
+    }
+}
+
+/// Tuning of the server generated by `app!`.
+/// It is set with the `server` key of `app!` or `App::server`.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    keep_alive: bool,
+    keep_alive_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    http2_only: bool,
+    max_connections: Option<usize>,
+    nodelay: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            keep_alive_timeout: None,
+            header_read_timeout: None,
+            http2_only: false,
+            max_connections: None,
+            nodelay: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Whether HTTP/1 connections are kept open between requests. Defaults to `true`
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// How long a connection can be idle between requests, before it is closed
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a client can take to send the head of a request, once it started sending it.
+    /// The connection is closed when it takes longer.
+    /// It only applies to HTTP/1, HTTP/2 connections also send frames between requests.
+    pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+        self.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Serves only HTTP/2, which is h2c without tls
+    pub fn http2_only(mut self, enabled: bool) -> Self {
+        self.http2_only = enabled;
+        self
+    }
+
+    /// New connections are not accepted, while there are `max` open ones
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on accepted connections
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+}
+
+struct Limit {
+    max: usize,
+    open: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+/// Counts as an open connection until dropped
+struct Permit(Arc<Limit>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+}
+
+impl Limit {
+    fn poll_acquire(self: &Arc<Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        self.waker.register(cx.waker());
+        let mut open = self.open.load(Ordering::SeqCst);
+        while open < self.max {
+            match self
+                .open
+                .compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return Poll::Ready(Permit(Arc::clone(self))),
+                Err(current) => open = current,
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Accepts the connections of all the sockets of an `App`
+pub struct Listener {
+    sockets: Vec<Socket>,
+    next: usize,
+    error_delay: Option<Delay>,
+    limit: Option<Arc<Limit>>,
+    permit: Option<Permit>,
+    config: ServerConfig,
+}
+
+impl Listener {
+    pub fn bind(addresses: &[Address], config: &ServerConfig) -> io::Result<Self> {
+        let mut sockets = vec![];
+        for address in addresses {
+            let bound = address.bind().map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not listen on `{}`: {}", address, e),
+                )
+            })?;
+            sockets.extend(bound);
+        }
+        if sockets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no address to listen on",
+            ));
+        }
+
+        let limit = config.max_connections.map(|max| {
+            Arc::new(Limit {
+                max,
+                open: AtomicUsize::new(0),
+                waker: AtomicWaker::new(),
+            })
+        });
+
+        Ok(Self {
+            sockets,
+            next: 0,
+            error_delay: None,
+            limit,
+            permit: None,
+            config: config.clone(),
+        })
+    }
+
+    /// The addresses of the sockets, with the ports picked by the os
+    pub fn local_addrs(&self) -> io::Result<Vec<Address>> {
+        self.sockets.iter().map(Socket::local_addr).collect()
+    }
+}
+
+/// Errors that only concern a single connection, which was closed before it was accepted
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+impl Accept for Listener {
+    type Conn = Conn<Stream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(delay) = &mut this.error_delay {
+            match Pin::new(delay).poll(cx) {
+                Poll::Ready(()) => this.error_delay = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if this.permit.is_none() {
+            if let Some(limit) = &this.limit {
+                match limit.poll_acquire(cx) {
+                    Poll::Ready(permit) => this.permit = Some(permit),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        // the sockets take turns, so a busy one doesn't starve the others
+        let len = this.sockets.len();
+        for i in 0..len {
+            let index = (this.next + i) % len;
+            loop {
+                match this.sockets[index].poll_accept(cx) {
+                    Poll::Ready(Ok(stream)) => {
+                        this.next = index + 1;
+                        if let Err(e) = stream.set_nodelay(this.config.nodelay) {
+                            log::warn!("could not set TCP_NODELAY err: {}", e);
+                        }
+                        return Poll::Ready(Some(Ok(Conn::new(
+                            stream,
+                            this.permit.take(),
+                            &this.config,
+                        ))));
+                    }
+                    Poll::Ready(Err(e)) if is_connection_error(&e) => continue,
+                    Poll::Ready(Err(e)) => {
+                        // like running out of file descriptors, so accepting is paused for a while
+                        log::error!("could not accept connection err: {}", e);
+                        let mut delay = delay_until(Instant::now() + ACCEPT_ERROR_DELAY);
+                        if Pin::new(&mut delay).poll(cx).is_pending() {
+                            this.error_delay = Some(delay);
+                            return Poll::Pending;
+                        }
+                    }
+                    Poll::Pending => break,
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+struct State {
+    in_flight: usize,
+    last_active: Instant,
+    reading_since: Option<Instant>,
+    http2: bool,
+    // the connection waiting for the requests to finish, to start its timers
+    waiting: Option<Waker>,
+}
+
+/// What a connection is doing, shared between it and the service handling its requests
+#[derive(Clone)]
+pub struct Activity(Arc<Mutex<State>>);
+
+/// Marks a request as in flight until dropped
+pub struct Busy(Activity);
+
+impl Activity {
+    fn new(http2: bool) -> Self {
+        Self(Arc::new(Mutex::new(State {
+            in_flight: 0,
+            last_active: Instant::now(),
+            reading_since: None,
+            http2,
+            waiting: None,
+        })))
+    }
+
+    /// Called once the connection is known to be HTTP/2, which has no header read timeout
+    pub(crate) fn http2(&self) {
+        let mut state = self.0.lock().expect("poisoned lock");
+        state.http2 = true;
+        state.reading_since = None;
+    }
+
+    /// Called with a request, once its headers are read.
+    /// The request is in flight until its response body is sent.
+    pub fn busy(&self) -> Busy {
+        let mut state = self.0.lock().expect("poisoned lock");
+        state.in_flight += 1;
+        state.reading_since = None;
+        Busy(self.clone())
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut state = (self.0).0.lock().expect("poisoned lock");
+        state.in_flight -= 1;
+        state.last_active = Instant::now();
+        if state.in_flight == 0 {
+            if let Some(waker) = state.waiting.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A connection with `Activity`
+pub trait Tracked {
+    fn activity(&self) -> Activity;
+}
+
+/// An accepted connection, closed when it is idle or reading a request for too long
+pub struct Conn<IO> {
+    io: IO,
+    activity: Activity,
+    keep_alive_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    timer: Option<Delay>,
+    // how much of `H2_PREFACE` was read, until it is known whether the connection is HTTP/2
+    preface: Option<usize>,
+    _permit: Option<Permit>,
+}
+
+impl<IO> Conn<IO> {
+    fn new(io: IO, permit: Option<Permit>, config: &ServerConfig) -> Self {
+        Self {
+            io,
+            activity: Activity::new(config.http2_only),
+            keep_alive_timeout: config.keep_alive_timeout,
+            header_read_timeout: config.header_read_timeout,
+            timer: None,
+            preface: if config.http2_only { None } else { Some(0) },
+            _permit: permit,
+        }
+    }
+
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// Checks the timeouts, while the connection waits for data
+    fn poll_timeout(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        loop {
+            let (at, expired) = {
+                let mut state = self.activity.0.lock().expect("poisoned lock");
+                if state.in_flight > 0 {
+                    state.waiting = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                match (state.reading_since, self.header_read_timeout) {
+                    (Some(since), Some(timeout)) => (
+                        since + timeout,
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out reading the request",
+                        )),
+                    ),
+                    (Some(_), None) => return Poll::Pending,
+                    (None, _) => match self.keep_alive_timeout {
+                        // the end of the stream closes the idle connection
+                        Some(timeout) => (state.last_active + timeout, Ok(0)),
+                        None => return Poll::Pending,
+                    },
+                }
+            };
+
+            let timer = match &mut self.timer {
+                Some(timer) => {
+                    if timer.deadline() != at {
+                        timer.reset(at);
+                    }
+                    timer
+                }
+                None => self.timer.get_or_insert(delay_until(at)),
+            };
+            match Pin::new(timer).poll(cx) {
+                Poll::Ready(()) if Instant::now() >= at => return Poll::Ready(expired),
+                Poll::Ready(()) => continue,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<IO> Tracked for Conn<IO> {
+    fn activity(&self) -> Activity {
+        self.activity.clone()
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for Conn<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) if n > 0 => {
+                if let Some(read) = this.preface {
+                    let len = n.min(H2_PREFACE.len() - read);
+                    this.preface = if buf[..len] != H2_PREFACE[read..read + len] {
+                        None
+                    } else if read + len == H2_PREFACE.len() {
+                        this.activity.http2();
+                        None
+                    } else {
+                        Some(read + len)
+                    };
+                }
+
+                let mut state = this.activity.0.lock().expect("poisoned lock");
+                let now = Instant::now();
+                state.last_active = now;
+                if state.in_flight == 0 && !state.http2 && state.reading_since.is_none() {
+                    state.reading_since = Some(now);
+                }
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending => this.poll_timeout(cx),
+            other => other,
+        }
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for Conn<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.io).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if n > 0 {
+                this.activity.0.lock().expect("poisoned lock").last_active = Instant::now();
+            }
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+/// A response body, which keeps its request in flight until it is sent.
+/// The request body can still be read meanwhile, so it doesn't count as the head of the next request.
+struct BusyBody {
+    body: Body,
+    _busy: Busy,
+}
+
+impl HttpBody for BusyBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.body).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<header::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Serves the connections of `incoming` with `service` until `signal` resolves, see `Shutdown::serve`
+pub async fn serve<I, F>(
+    incoming: I,
+    service: AppService,
+    config: ServerConfig,
+    shutdown: Shutdown,
+    signal: F,
+) -> Result<(), hyper::Error>
+where
+    I: Accept,
+    I::Conn: Tracked + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let deadline = shutdown.deadline();
+    let pending = shutdown.pending();
+    let make_svc = make_service_fn(move |conn: &I::Conn| {
+        let activity = conn.activity();
+        let service = service.clone();
+        let deadline = deadline.clone();
+        let pending = pending.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |r: Request<Body>| {
+                let busy = activity.busy();
+                let res = deadline
+                    .clone()
+                    .run(pending.clone().scope(service.handle(r)));
+                async move {
+                    let res = res.await;
+                    res.map(|res| res.map(|body| BusyBody { body, _busy: busy }))
+                }
+            }))
+        }
+    });
+
+    shutdown
+        .serve(
+            |signal| {
+                hyper::Server::builder(incoming)
+                    .http1_keepalive(config.keep_alive)
+                    .http2_only(config.http2_only)
+                    .serve(make_svc)
+                    .with_graceful_shutdown(signal)
+            },
+            signal,
+        )
+        .await
+}
@@ -0,0 +1,237 @@
+//! Graceful shutdown for the server generated by `app!`.
+//! Once the shutdown signal resolves, no new connections are accepted.
+//! In-flight requests and the jobs they started with `darpi::spawn` get until the deadline to finish.
+//! Requests still running at the deadline are answered with `503 Service Unavailable`.
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::{header, StatusCode};
+use hyper::{Body, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch, Notify};
+use tokio::time::{timeout_at, Instant};
+
+/// The default time given to in-flight requests and jobs after the shutdown signal
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    static CURRENT: Pending;
+}
+
+/// The background jobs of one server, which its shutdown waits for
+#[derive(Clone, Default)]
+pub(crate) struct Pending(Arc<Jobs>);
+
+#[derive(Default)]
+struct Jobs {
+    count: AtomicUsize,
+    notify: Notify,
+}
+
+impl Pending {
+    /// Runs `f`, tracking the jobs it spawns
+    pub(crate) async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    fn count(&self) -> usize {
+        self.0.count.load(Ordering::SeqCst)
+    }
+
+    async fn drained(&self) {
+        while self.count() > 0 {
+            self.0.notify.notified().await;
+        }
+    }
+}
+
+/// Marks a background job as pending until dropped
+pub(crate) struct Guard(Pending);
+
+impl Guard {
+    /// Runs `f` with the jobs it spawns tracked like the one of this guard
+    pub(crate) async fn scope<F: Future>(&self, f: F) -> F::Output {
+        self.0.clone().scope(f).await
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if (self.0).0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            (self.0).0.notify.notify();
+        }
+    }
+}
+
+/// Tracks a job of the server handling the current request.
+/// Jobs spawned outside of a server are not waited for.
+pub(crate) fn track() -> Option<Guard> {
+    CURRENT
+        .try_with(|pending| {
+            pending.0.count.fetch_add(1, Ordering::SeqCst);
+            Guard(pending.clone())
+        })
+        .ok()
+}
+
+/// Resolves on `SIGTERM` or `SIGINT`.
+/// On platforms without unix signals, it resolves on ctrl-c.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::interrupt()),
+        ) {
+            (Ok(mut term), Ok(mut int)) => {
+                tokio::select! {
+                    _ = term.recv() => {},
+                    _ = int.recv() => {},
+                }
+                return;
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::warn!("could not listen for unix signals err: {}", e);
+            }
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::warn!("could not listen for ctrl-c err: {}", e);
+        futures::future::pending::<()>().await;
+    }
+}
+
+/// In-flight requests still running this long after the deadline are dropped
+const CANCEL_GRACE: Duration = Duration::from_secs(1);
+
+/// Cancels in-flight requests once the shutdown deadline is reached
+#[derive(Clone)]
+pub struct Deadline(watch::Receiver<bool>);
+
+impl Deadline {
+    /// Runs the request, unless the deadline is reached first.
+    /// Cancelled requests are answered with `503 Service Unavailable`.
+    pub async fn run<F>(mut self, request: F) -> Result<Response<Body>, Infallible>
+    where
+        F: Future<Output = Result<Response<Body>, Infallible>>,
+    {
+        let expired = async {
+            loop {
+                match self.0.recv().await {
+                    Some(true) => return,
+                    Some(false) => continue,
+                    None => futures::future::pending::<()>().await,
+                }
+            }
+        };
+
+        tokio::select! {
+            res = request => res,
+            _ = expired => Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::CONNECTION, "close")
+                .body(Body::empty())
+                .expect("this cannot happen")),
+        }
+    }
+}
+
+pub struct Shutdown {
+    timeout: Duration,
+    tx: watch::Sender<bool>,
+    rx: watch::Receiver<bool>,
+    pending: Pending,
+}
+
+impl Shutdown {
+    /// `timeout` is how long in-flight requests and background jobs get after the shutdown signal
+    pub fn new(timeout: Duration) -> Self {
+        let (tx, rx) = watch::channel(false);
+        Self {
+            timeout,
+            tx,
+            rx,
+            pending: Pending::default(),
+        }
+    }
+
+    pub fn deadline(&self) -> Deadline {
+        Deadline(self.rx.clone())
+    }
+
+    /// The jobs spawned by the requests of this server
+    pub(crate) fn pending(&self) -> Pending {
+        self.pending.clone()
+    }
+
+    /// Runs the server returned by `serve` until `signal` resolves.
+    /// `serve` is given the future to pass to `hyper::Server::with_graceful_shutdown`.
+    /// After the signal, it waits for in-flight requests and background jobs until the deadline.
+    pub async fn serve<S, F>(
+        self,
+        serve: impl FnOnce(BoxFuture<'static, ()>) -> S,
+        signal: F,
+    ) -> Result<(), hyper::Error>
+    where
+        S: Future<Output = Result<(), hyper::Error>>,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, mut rx) = oneshot::channel();
+        let server = serve(
+            async move {
+                signal.await;
+                let _ = tx.send(Instant::now());
+            }
+            .boxed(),
+        );
+        futures::pin_mut!(server);
+
+        enum First {
+            Server(Result<(), hyper::Error>),
+            Signal(Instant),
+        }
+
+        let first = tokio::select! {
+            res = &mut server => First::Server(res),
+            Ok(at) = &mut rx => First::Signal(at),
+        };
+
+        let (res, deadline) = match first {
+            First::Server(res) => match rx.try_recv() {
+                Ok(at) => (res, at + self.timeout),
+                // the server stopped before the shutdown signal
+                Err(_) => return res,
+            },
+            First::Signal(at) => {
+                log::info!("shutting down");
+                let deadline = at + self.timeout;
+                let res = match timeout_at(deadline, &mut server).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        log::warn!("shutdown deadline reached, cancelling in-flight requests");
+                        let _ = self.tx.broadcast(true);
+                        timeout_at(deadline + CANCEL_GRACE, server)
+                            .await
+                            .unwrap_or(Ok(()))
+                    }
+                };
+                (res, deadline)
+            }
+        };
+
+        if timeout_at(deadline, self.pending.drained()).await.is_err() {
+            log::warn!(
+                "shutdown deadline reached, dropping {} background jobs",
+                self.pending.count()
+            );
+        }
+        res
+    }
+}
@@ -0,0 +1,71 @@
+//! Values shared by every request of an app, registered with `state: { Type: value }` in `app!`.
+//! Handlers and request middleware receive them with `#[state] value: Arc<Type>`.
+//! The error of such middleware has to implement `From<MissingState>`,
+//! which it returns when called outside of an app, without the value.
+
+use crate::response::ResponderError;
+use http::request::Parts as RequestParts;
+use http::StatusCode;
+use hyper::{Body, Request};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Implemented by `app!` for a type it generates, for every type of its `state`
+pub trait HasState<T: Send + Sync + 'static> {}
+
+/// Implemented by handlers, middleware and `routes!` for every `S` that has all the values
+/// they ask for with `#[state]`. `app!` requires it for its `S` of everything registered in it,
+/// so asking for a type the app doesn't have fails to compile.
+pub trait UsesState<S> {}
+
+/// The state of an app, stored in the extensions of its requests
+#[derive(Clone, Default)]
+pub struct State(Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>);
+
+impl State {
+    /// Adds `value`, replacing the previous one of its type
+    pub fn with<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+        Arc::make_mut(&mut self.0).insert(TypeId::of::<T>(), Arc::new(value));
+        self
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| Arc::clone(value).downcast::<T>().ok())
+    }
+
+    pub fn insert_into(&self, req: &mut Request<Body>) {
+        req.extensions_mut().insert(self.clone());
+    }
+
+    /// The value of the request's app.
+    /// Outside of an app, like in `darpi::test`, it is missing unless inserted with `insert_into`.
+    pub fn of<T: Send + Sync + 'static>(parts: &RequestParts) -> Result<Arc<T>, MissingState> {
+        parts
+            .extensions
+            .get::<State>()
+            .and_then(State::get::<T>)
+            .ok_or(MissingState(std::any::type_name::<T>()))
+    }
+}
+
+/// A value asked for by `#[state]`, which the request doesn't have
+#[derive(Debug)]
+pub struct MissingState(&'static str);
+
+impl fmt::Display for MissingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not in the state of the app", self.0)
+    }
+}
+
+impl std::error::Error for MissingState {}
+
+impl ResponderError for MissingState {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
@@ -0,0 +1,330 @@
+//! Testing utilities.
+//! `TestClient` sends requests through the pipeline of an `app!` in memory, without a listener.
+//! Middleware and job factories can be called on their own with `request_middleware`,
+//! `response_middleware`, `request_job` and `response_job`.
+
+use crate::job::Job;
+use crate::server::AppService;
+use crate::{RequestJobFactory, RequestMiddleware, ResponseJobFactory, ResponseMiddleware};
+use http::header::{HeaderName, CONTENT_TYPE};
+use hyper::body::Bytes;
+use hyper::header::{AsHeaderName, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use shaku::{Module, ModuleBuilder};
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Sends requests through routing, middleware, handlers and jobs of an `app!`.
+///
+/// ```ignore
+/// let client = TestClient::new(app.service());
+/// let resp = client.get("/hello").await;
+/// resp.assert_status(StatusCode::OK);
+/// assert_eq!(resp.text().await, "hello");
+/// ```
+#[derive(Clone)]
+pub struct TestClient {
+    service: AppService,
+}
+
+impl TestClient {
+    pub fn new(service: AppService) -> Self {
+        Self { service }
+    }
+
+    pub async fn send(&self, req: Request<Body>) -> TestResponse {
+        match self.service.handle(req).await {
+            Ok(resp) => TestResponse(resp),
+            Err(e) => match e {},
+        }
+    }
+
+    /// Sends a request with an empty body
+    pub async fn request(&self, method: Method, uri: &str) -> TestResponse {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .expect("invalid request");
+        self.send(req).await
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.request(Method::DELETE, uri).await
+    }
+
+    /// Sends `body` as `application/json`
+    pub async fn post_json<T: serde::Serialize>(&self, uri: &str, body: &T) -> TestResponse {
+        self.json(Method::POST, uri, body).await
+    }
+
+    /// Sends `body` as `application/json`
+    pub async fn put_json<T: serde::Serialize>(&self, uri: &str, body: &T) -> TestResponse {
+        self.json(Method::PUT, uri, body).await
+    }
+
+    async fn json<T: serde::Serialize>(&self, method: Method, uri: &str, body: &T) -> TestResponse {
+        let body = serde_json::to_vec(body).expect("could not serialize body");
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("invalid request");
+        self.send(req).await
+    }
+}
+
+/// The response of a `TestClient` with helpers for assertions
+#[derive(Debug)]
+pub struct TestResponse(Response<Body>);
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.0.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap<HeaderValue> {
+        self.0.headers()
+    }
+
+    /// The header value, if it is present and valid utf-8
+    pub fn header<K: AsHeaderName>(&self, name: K) -> Option<&str> {
+        self.0.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.status(), status, "unexpected status");
+        self
+    }
+
+    pub fn assert_header<K>(&self, name: K, value: &str) -> &Self
+    where
+        K: AsHeaderName + Debug + Clone,
+    {
+        assert_eq!(
+            self.header(name.clone()),
+            Some(value),
+            "unexpected value for header {:?}",
+            name
+        );
+        self
+    }
+
+    pub async fn bytes(self) -> Bytes {
+        hyper::body::to_bytes(self.0.into_body())
+            .await
+            .expect("could not read the response body")
+    }
+
+    pub async fn text(self) -> String {
+        String::from_utf8(self.bytes().await.to_vec()).expect("the response body is not utf-8")
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> T {
+        serde_json::from_slice(&self.bytes().await)
+            .expect("could not deserialize the response body")
+    }
+
+    pub fn into_inner(self) -> Response<Body> {
+        self.0
+    }
+}
+
+/// Builds a `Request<Body>` for calling middleware and job factories.
+///
+/// ```ignore
+/// let mut req = RequestBuilder::post("/login").json(&credentials).build();
+/// let res = request_middleware::<authorize<Role>, _>(&mut req, module, Role::Admin).await;
+/// ```
+pub struct RequestBuilder {
+    builder: http::request::Builder,
+    body: Body,
+}
+
+impl RequestBuilder {
+    pub fn new(method: Method, uri: &str) -> Self {
+        Self {
+            builder: Request::builder().method(method).uri(uri),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn get(uri: &str) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    pub fn post(uri: &str) -> Self {
+        Self::new(Method::POST, uri)
+    }
+
+    pub fn put(uri: &str) -> Self {
+        Self::new(Method::PUT, uri)
+    }
+
+    pub fn delete(uri: &str) -> Self {
+        Self::new(Method::DELETE, uri)
+    }
+
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the body to `body` as `application/json`
+    pub fn json<T: Serialize>(self, body: &T) -> Self {
+        let body = serde_json::to_vec(body).expect("could not serialize body");
+        self.header(CONTENT_TYPE, "application/json").body(body)
+    }
+
+    pub fn build(self) -> Request<Body> {
+        self.builder.body(self.body).expect("invalid request")
+    }
+}
+
+/// Builds a `Response<Body>` for calling response middleware and job factories
+pub struct ResponseBuilder {
+    builder: http::response::Builder,
+    body: Body,
+}
+
+impl ResponseBuilder {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            builder: Response::builder().status(status),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(StatusCode::OK)
+    }
+
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Into<http::Error>,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Into<http::Error>,
+    {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the body to `body` as `application/json`
+    pub fn json<T: Serialize>(self, body: &T) -> Self {
+        let body = serde_json::to_vec(body).expect("could not serialize body");
+        self.header(CONTENT_TYPE, "application/json").body(body)
+    }
+
+    pub fn build(self) -> Response<Body> {
+        self.builder.body(self.body).expect("invalid response")
+    }
+}
+
+/// Builds the module `M`, with components or providers replaced by `overrides`.
+///
+/// ```ignore
+/// let module = mock_module::<Container>(|b| {
+///     b.with_component_override::<dyn UserRepository>(Box::new(MockRepository))
+/// });
+/// ```
+pub fn mock_module<M>(overrides: impl FnOnce(ModuleBuilder<M>) -> ModuleBuilder<M>) -> Arc<M>
+where
+    M: Module<Submodules = ()>,
+{
+    Arc::new(overrides(ModuleBuilder::with_submodules(())).build())
+}
+
+/// Calls the request middleware `T` with `req`, as `app!` would.
+/// Changes of the middleware to the request are kept in `req`.
+pub async fn request_middleware<T, C>(
+    req: &mut Request<Body>,
+    container: Arc<C>,
+    args: T::HandlerArgs,
+) -> Result<T::Type, T::Error>
+where
+    T: RequestMiddleware<C>,
+    C: 'static + Sync + Send,
+{
+    let (mut parts, mut body) = std::mem::take(req).into_parts();
+    let res = T::call(&mut parts, container, &mut body, args).await;
+    *req = Request::from_parts(parts, body);
+    res
+}
+
+/// Calls the response middleware `T` with `resp`, as `app!` would
+pub async fn response_middleware<T, C>(
+    resp: &mut Response<Body>,
+    container: Arc<C>,
+    args: T::HandlerArgs,
+) -> Result<T::Type, T::Error>
+where
+    T: ResponseMiddleware<C>,
+    C: 'static + Sync + Send,
+{
+    T::call(resp, container, args).await
+}
+
+/// Calls the request job factory `T` with `req` and returns the job, without running it
+pub async fn request_job<T, C>(
+    req: &mut Request<Body>,
+    container: Arc<C>,
+    args: T::HandlerArgs,
+) -> T::Return
+where
+    T: RequestJobFactory<C>,
+    C: 'static + Sync + Send,
+{
+    let (parts, body) = std::mem::take(req).into_parts();
+    let job = T::call(&parts, container, &body, args).await;
+    *req = Request::from_parts(parts, body);
+    job
+}
+
+/// Calls the response job factory `T` with `resp` and returns the job, without running it
+pub async fn response_job<T, C>(
+    resp: &Response<Body>,
+    container: Arc<C>,
+    args: T::HandlerArgs,
+) -> T::Return
+where
+    T: ResponseJobFactory<C>,
+    C: 'static + Sync + Send,
+{
+    T::call(resp, container, args).await
+}
+
+/// Runs the job to completion and returns its result
+pub async fn run_job<T>(job: impl Into<Job<T>>) -> T
+where
+    T: Send + 'static,
+{
+    match crate::oneshot(job).await {
+        Ok(recv) => recv.await.expect("the job did not finish"),
+        Err(_) => panic!("could not start the job"),
+    }
+}
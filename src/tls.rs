@@ -0,0 +1,244 @@
+//! HTTPS for the server generated by `app!`, through rustls.
+//! Certificates are reloaded in the background after their provider reports a change.
+
+use crate::listen::Stream;
+use crate::server::{Activity, Conn, Listener, Tracked};
+use futures::{future, StreamExt};
+use hyper::server::accept::{self, Accept};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, Session};
+use tokio_rustls::server::TlsStream;
+
+pub use tokio_rustls::rustls::{Certificate, PrivateKey};
+
+/// How often the provider is asked whether the certificates changed
+const RELOAD_CHECK: Duration = Duration::from_secs(1);
+
+/// How many handshakes can be in progress at the same time
+const MAX_HANDSHAKES: usize = 256;
+
+/// How long a client has to complete its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Provides the certificate chain and private key for the server.
+/// It can be registered as a component in the container and used in `app!`
+/// with `tls: { provider: dyn darpi::tls::CertificateProvider }`.
+pub trait CertificateProvider: Send + Sync {
+    fn load(&self) -> io::Result<(Vec<Certificate>, PrivateKey)>;
+
+    /// The certificates are loaded again, when this value changes.
+    /// `None` means they never change.
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// A PEM encoded certificate chain and private key.
+/// The private key can be either PKCS8 or RSA.
+pub struct PemFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl PemFiles {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    fn read_key(&self) -> io::Result<PrivateKey> {
+        let pkcs8 = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&self.key)?))
+            .map_err(|_| invalid(format!("invalid private key {}", self.key.display())))?;
+        if let Some(key) = pkcs8.into_iter().next() {
+            return Ok(key);
+        }
+
+        let rsa = pemfile::rsa_private_keys(&mut BufReader::new(File::open(&self.key)?))
+            .map_err(|_| invalid(format!("invalid private key {}", self.key.display())))?;
+        rsa.into_iter()
+            .next()
+            .ok_or_else(|| invalid(format!("no private key in {}", self.key.display())))
+    }
+}
+
+impl CertificateProvider for PemFiles {
+    fn load(&self) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+        let certs = pemfile::certs(&mut BufReader::new(File::open(&self.cert)?))
+            .map_err(|_| invalid(format!("invalid certificate {}", self.cert.display())))?;
+        if certs.is_empty() {
+            return Err(invalid(format!(
+                "no certificates in {}",
+                self.cert.display()
+            )));
+        }
+        Ok((certs, self.read_key()?))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let cert = fs::metadata(&self.cert).and_then(|m| m.modified()).ok()?;
+        let key = fs::metadata(&self.key).and_then(|m| m.modified()).ok()?;
+        Some(cert.max(key))
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn certified_key<P>(provider: &P) -> io::Result<CertifiedKey>
+where
+    P: CertificateProvider + ?Sized,
+{
+    let (certs, key) = provider.load()?;
+    let key = sign::any_supported_type(&key)
+        .map_err(|_| invalid("unsupported private key type".to_string()))?;
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+struct Resolver<P: ?Sized> {
+    provider: Arc<P>,
+    current: RwLock<(Option<SystemTime>, CertifiedKey)>,
+}
+
+/// Checks whether the certificates changed, out of the handshakes
+trait Reload: Send + Sync {
+    fn reload_if_changed(&self);
+}
+
+impl<P> Reload for Resolver<P>
+where
+    P: CertificateProvider + ?Sized,
+{
+    fn reload_if_changed(&self) {
+        let modified = self.provider.modified();
+        if modified == self.current.read().expect("poisoned lock").0 {
+            return;
+        }
+
+        match certified_key(&*self.provider) {
+            Ok(key) => {
+                log::info!("reloaded tls certificates");
+                *self.current.write().expect("poisoned lock") = (modified, key);
+            }
+            Err(e) => log::warn!("could not reload tls certificates err: {}", e),
+        }
+    }
+}
+
+impl<P> ResolvesServerCert for Resolver<P>
+where
+    P: CertificateProvider + ?Sized + 'static,
+{
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().expect("poisoned lock").1.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: tokio_rustls::TlsAcceptor,
+    resolver: Arc<dyn Reload>,
+}
+
+impl TlsAcceptor {
+    /// Loads the certificates, so invalid ones are reported before the server starts
+    pub fn new<P>(provider: Arc<P>) -> io::Result<Self>
+    where
+        P: CertificateProvider + ?Sized + 'static,
+    {
+        let modified = provider.modified();
+        let key = certified_key(&*provider)?;
+
+        let resolver = Arc::new(Resolver {
+            provider,
+            current: RwLock::new((modified, key)),
+        });
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = resolver.clone();
+        config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+
+        Ok(Self {
+            acceptor: tokio_rustls::TlsAcceptor::from(Arc::new(config)),
+            resolver,
+        })
+    }
+
+    /// Accepts the connections of `incoming` over TLS.
+    /// Handshakes run concurrently, the ones that take too long are dropped and
+    /// the failed ones are only logged.
+    /// While the connections are accepted, a background task reloads the changed certificates.
+    pub fn incoming(
+        self,
+        mut incoming: Listener,
+    ) -> impl Accept<Conn = TlsStream<Conn<Stream>>, Error = io::Error> {
+        tokio::spawn(reload(Arc::downgrade(&self.resolver)));
+
+        let acceptor = self.acceptor;
+        let conns = futures::stream::poll_fn(move |cx| Pin::new(&mut incoming).poll_accept(cx))
+            .filter_map(|conn| {
+                future::ready(match conn {
+                    Ok(conn) => Some(conn),
+                    Err(e) => {
+                        log::warn!("could not accept connection err: {}", e);
+                        None
+                    }
+                })
+            })
+            .map(move |conn| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(conn)))
+            .buffer_unordered(MAX_HANDSHAKES)
+            .filter_map(|conn| {
+                future::ready(match conn {
+                    Ok(Ok(conn)) => {
+                        // the preface of HTTP/2 is encrypted, so `Conn` can't see it
+                        if conn.get_ref().1.get_alpn_protocol() == Some(b"h2") {
+                            conn.get_ref().0.activity().http2();
+                        }
+                        Some(Ok::<_, io::Error>(conn))
+                    }
+                    Ok(Err(e)) => {
+                        log::debug!("tls handshake failed err: {}", e);
+                        None
+                    }
+                    Err(_) => {
+                        log::debug!("tls handshake timed out");
+                        None
+                    }
+                })
+            });
+        accept::from_stream(conns)
+    }
+}
+
+/// Checks the certificates every `RELOAD_CHECK`, until the acceptors are dropped with their server
+async fn reload(resolver: Weak<dyn Reload>) {
+    let mut interval = tokio::time::interval(RELOAD_CHECK);
+    // the first tick completes immediately
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let resolver = match resolver.upgrade() {
+            Some(resolver) => resolver,
+            None => return,
+        };
+        // the provider may read files
+        let checked = tokio::task::spawn_blocking(move || resolver.reload_if_changed()).await;
+        if let Err(e) = checked {
+            log::warn!("could not reload tls certificates err: {}", e);
+        }
+    }
+}
+
+impl<IO> Tracked for TlsStream<Conn<IO>> {
+    fn activity(&self) -> Activity {
+        self.get_ref().0.activity()
+    }
+}
@@ -0,0 +1,68 @@
+//! Helpers shared by the integration tests, which each use only some of them
+
+#![allow(dead_code)]
+
+use darpi::hyper;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Notify;
+
+/// Counts what background jobs did, so tests wait for them instead of sleeping
+pub struct Counter {
+    count: AtomicUsize,
+    changed: Notify,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Self {
+            count: AtomicUsize::new(0),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn add(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.changed.notify();
+    }
+
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the count to reach `n` and returns it
+    pub async fn reached(&self, n: usize) -> usize {
+        let wait = async {
+            while self.get() < n {
+                self.changed.notified().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("the jobs did not run");
+        self.get()
+    }
+}
+
+/// The body of a response as text
+pub async fn text(resp: hyper::Response<hyper::Body>) -> String {
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Sends `GET path` over `stream` by hand and returns the whole response,
+/// for the listeners `hyper::Client` can't connect to
+pub async fn get_raw<S>(mut stream: S, path: &str) -> String
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "GET {} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    resp
+}
@@ -0,0 +1,177 @@
+use darpi::config::{Config, ConfigError, Settings};
+use darpi::test::TestClient;
+use darpi::{app, handler, Method, StatusCode};
+use darpi_middleware::auth::{
+    Algorithm, JwtAlgorithmProvider, JwtAlgorithmProviderImpl, JwtConfig, JwtSecretProviderImpl,
+};
+use serde::Deserialize;
+use shaku::module;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Deserialize)]
+pub struct AppConfig {
+    address: String,
+    workers: usize,
+    name: String,
+    jwt: JwtConfig,
+}
+
+impl Settings for AppConfig {
+    fn validate(&self) -> Result<(), String> {
+        match self.workers {
+            0 => Err("`workers` can't be 0".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+module! {
+    Container {
+        components = [JwtSecretProviderImpl, JwtAlgorithmProviderImpl],
+        providers = [],
+    }
+}
+
+fn make_container(config: &AppConfig) -> Container {
+    Container::builder()
+        .with_component_parameters::<JwtSecretProviderImpl>((&config.jwt).into())
+        .with_component_parameters::<JwtAlgorithmProviderImpl>((&config.jwt).into())
+        .build()
+}
+
+#[handler({
+    container: Container
+})]
+async fn algorithm(#[inject] provider: Arc<dyn JwtAlgorithmProvider>) -> String {
+    format!("{:?}", provider.algorithm().await)
+}
+
+fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("darpi-config-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, content) in files {
+        std::fs::write(dir.join(file), content).unwrap();
+    }
+    dir
+}
+
+#[tokio::test]
+async fn layered_config() {
+    let dir = config_dir(
+        "layered",
+        &[
+            (
+                "default.toml",
+                "address = \"127.0.0.1:0\"\nworkers = 2\nname = \"darpi\"\n\n[jwt]\nsecret = \"secret\"\n",
+            ),
+            ("production.yaml", "workers: 4\njwt:\n  algorithm: HS512\n"),
+            ("local.json", "{\"name\": \"local\"}"),
+        ],
+    );
+    let config: AppConfig = Config::new()
+        .file(dir.join("default.toml"))
+        .optional_file(dir.join("production.yaml"))
+        .optional_file(dir.join("staging.yaml"))
+        .file(dir.join("local.json"))
+        .env_prefix(Some("LAYERED"))
+        .env(vec![
+            ("LAYERED_WORKERS", "8"),
+            ("LAYERED_JWT__SECRET", "1234"),
+            ("OTHER_WORKERS", "16"),
+        ])
+        .load()
+        .unwrap();
+
+    // every layer overrides only its keys
+    assert_eq!(config.workers, 8);
+    assert_eq!(config.name, "local");
+    assert_eq!(config.jwt.secret, "1234");
+    assert_eq!(config.jwt.algorithm, Algorithm::HS512);
+
+    let app = app!({
+        address: config.address,
+        container: {
+            factory: make_container(&config),
+            type: Container
+        },
+        handlers: [{
+            route: "/algorithm",
+            method: Method::GET,
+            handler: algorithm
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.get("/algorithm").await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.text().await, "HS512");
+}
+
+#[test]
+fn invalid_config() {
+    let dir = config_dir(
+        "invalid",
+        &[
+            (
+                "default.toml",
+                "address = \"127.0.0.1:0\"\nworkers = 0\nname = \"darpi\"\n\n[jwt]\nsecret = \"secret\"\n",
+            ),
+            ("broken.yaml", "workers: [4\n"),
+            ("config.ini", "workers = 4\n"),
+            (
+                "empty_secret.yaml",
+                "address: 127.0.0.1:0\nworkers: 4\nname: darpi\njwt:\n  secret: \"\"\n",
+            ),
+            (
+                "no_secret.yaml",
+                "address: 127.0.0.1:0\nworkers: 4\nname: darpi\njwt:\n  algorithm: HS512\n",
+            ),
+        ],
+    );
+    let config = || {
+        Config::new()
+            .env_prefix(None)
+            .file(dir.join("default.toml"))
+    };
+
+    match config().load::<AppConfig>() {
+        Err(ConfigError::Invalid(e)) => assert_eq!(e, "`workers` can't be 0"),
+        r => panic!("expected invalid config, got {:?}", r),
+    }
+    match config().file(dir.join("missing.toml")).load::<AppConfig>() {
+        Err(ConfigError::Read(path, _)) => assert_eq!(path, dir.join("missing.toml")),
+        r => panic!("expected missing file, got {:?}", r),
+    }
+    match config().file(dir.join("broken.yaml")).load::<AppConfig>() {
+        Err(ConfigError::Parse(path, _)) => assert_eq!(path, dir.join("broken.yaml")),
+        r => panic!("expected parse error, got {:?}", r),
+    }
+    match config().file(dir.join("config.ini")).load::<AppConfig>() {
+        Err(ConfigError::Format(path)) => assert_eq!(path, dir.join("config.ini")),
+        r => panic!("expected unknown format, got {:?}", r),
+    }
+    match Config::new().env_prefix(None).load::<AppConfig>() {
+        Err(ConfigError::Deserialize(e)) => assert!(e.to_string().contains("missing field")),
+        r => panic!("expected missing fields, got {:?}", r),
+    }
+    // the jwt secret has no default
+    match Config::new()
+        .env_prefix(None)
+        .file(dir.join("no_secret.yaml"))
+        .load::<AppConfig>()
+    {
+        Err(ConfigError::Deserialize(e)) => assert_eq!(e.to_string(), "missing field `secret`"),
+        r => panic!("expected a missing secret, got {:?}", r),
+    }
+    match Config::new()
+        .env_prefix(None)
+        .file(dir.join("empty_secret.yaml"))
+        .load::<AppConfig>()
+    {
+        Err(ConfigError::Deserialize(e)) => {
+            assert_eq!(e.to_string(), "the jwt `secret` can't be empty")
+        }
+        r => panic!("expected an empty secret, got {:?}", r),
+    }
+}
@@ -0,0 +1,65 @@
+use darpi::job::CpuJob;
+use darpi::test::TestClient;
+use darpi::{app, handler, Method, StatusCode};
+
+#[handler]
+async fn thread() -> String {
+    let name = || {
+        std::thread::current()
+            .name()
+            .unwrap_or_default()
+            .to_string()
+    };
+    darpi::oneshot(CpuJob::from(name))
+        .await
+        .expect("could not start job")
+        .await
+        .expect("job failed")
+}
+
+#[tokio::test]
+async fn cpu_pool() {
+    let stack_size = 4 * 1024 * 1024;
+    let app = app!({
+        address: "127.0.0.1:0",
+        cpu_pool: {
+            threads: 2,
+            thread_name: "test-cpu",
+            stack_size: stack_size
+        },
+        handlers: [{
+            route: "/thread",
+            method: Method::GET,
+            handler: thread
+        }]
+    });
+    let client = TestClient::new(app.service());
+    let resp = client.get("/thread").await;
+    resp.assert_status(StatusCode::OK);
+    let name = resp.text().await;
+    assert!(name.starts_with("test-cpu-"), "{}", name);
+
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/thread",
+            method: Method::GET,
+            handler: thread
+        }]
+    });
+    let client = TestClient::new(app.service());
+    let name = client.get("/thread").await.text().await;
+    assert!(name.starts_with("darpi-cpu-"), "{}", name);
+
+    // binding several apps in one process doesn't panic
+    let _first = app.bind().expect("could not bind");
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/thread",
+            method: Method::GET,
+            handler: thread
+        }]
+    });
+    let _second = app.bind().expect("could not bind");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::text;
+use darpi::{app, handler, header, hyper, middleware, Body, Json, Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NotFound {
+    error: String,
+}
+
+#[handler]
+async fn index() -> &'static str {
+    "index"
+}
+
+#[handler]
+async fn not_found(#[request_parts] rp: &darpi::RequestParts) -> Json<NotFound> {
+    Json(NotFound {
+        error: format!("{} not found", rp.uri.path()),
+    })
+}
+
+#[middleware(Response)]
+async fn served_by(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-served-by", header::HeaderValue::from_static("darpi"));
+    Ok(())
+}
+
+async fn get(uri: &str) -> (StatusCode, Option<String>, String) {
+    let resp = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .expect("request failed");
+    let status = resp.status();
+    let server = resp
+        .headers()
+        .get("x-served-by")
+        .map(|v| v.to_str().unwrap().to_string());
+    (status, server, text(resp).await)
+}
+
+#[tokio::test]
+async fn fallback() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            request: [],
+            response: [served_by]
+        },
+        handlers: [{
+            route: "/",
+            method: Method::GET,
+            handler: index
+        }],
+        fallback: not_found
+    });
+    let server = app.bind().expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    assert_eq!(
+        get(&format!("{}/", base)).await,
+        (
+            StatusCode::OK,
+            Some("darpi".to_string()),
+            "index".to_string()
+        )
+    );
+    assert_eq!(
+        get(&format!("{}/nope", base)).await,
+        (
+            StatusCode::OK,
+            Some("darpi".to_string()),
+            r#"{"error":"/nope not found"}"#.to_string()
+        )
+    );
+}
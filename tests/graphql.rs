@@ -1,7 +1,7 @@
 use async_graphql::connection::{query, Connection, Edge, EmptyFields};
 use async_graphql::{Context, Enum, Interface, Object};
 use async_graphql::{EmptyMutation, EmptySubscription, Schema};
-use darpi::{app, from_path, handler, logger::DefaultFormat, Method};
+use darpi::{app, from_path, handler, hyper, logger::DefaultFormat, Method, StatusCode};
 use darpi_graphql::{GraphQLBody, MultipartOptionsProviderImpl, Request, Response};
 use darpi_middleware::{body_size_limit, log_request, log_response};
 use env_logger;
@@ -465,11 +465,11 @@ pub(crate) async fn do_something1(#[query] p: Option<Name>) -> String {
 //
 // //RUST_LOG=darpi=info cargo test --test graphql -- --nocapture
 #[tokio::test]
-async fn main() -> Result<(), darpi::Error> {
+async fn main() -> Result<(), darpi::server::Error> {
     env_logger::builder().is_test(true).try_init().unwrap();
 
-    app!({
-        address: "127.0.0.1:3000",
+    let server = app!({
+        address: "127.0.0.1:0",
         container: {
             factory: make_container(),
             type: Container
@@ -492,6 +492,21 @@ async fn main() -> Result<(), darpi::Error> {
             handler: index_post
         }]
     })
-    .run()
-    .await
+    .bind()?;
+    let uri = format!("http://{}/", server.local_addr());
+    // the server ends with the runtime of the test
+    tokio::spawn(server);
+
+    let req = hyper::Request::post(uri)
+        .header("content-type", "application/json")
+        .body(hyper::Body::from(r#"{"query":"{hero(episode: EMPIRE){name}}"}"#))
+        .unwrap();
+    let resp = hyper::Client::new()
+        .request(req)
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(body, r#"{"data":{"hero":{"name":"Luke Skywalker"}}}"#);
+    Ok(())
 }
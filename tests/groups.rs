@@ -0,0 +1,153 @@
+mod common;
+
+use common::text;
+use darpi::response::ResponderError;
+use darpi::{
+    app, handler, header, hyper, middleware, Body, Method, RequestParts, Response, StatusCode,
+};
+use std::convert::Infallible;
+
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "forbidden")
+    }
+}
+
+impl ResponderError for Forbidden {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+#[middleware(Request)]
+async fn authorize(
+    #[request_parts] rp: &RequestParts,
+    #[handler] role: &'static str,
+) -> Result<(), Forbidden> {
+    match rp.headers.get("x-role") {
+        Some(r) if r == role => Ok(()),
+        _ => Err(Forbidden),
+    }
+}
+
+#[middleware(Response)]
+async fn tag(
+    #[response] r: &mut Response<Body>,
+    #[handler] value: &'static str,
+) -> Result<(), Infallible> {
+    let tags = match r.headers().get("x-tags") {
+        Some(t) => format!("{},{}", t.to_str().unwrap(), value),
+        None => value.to_string(),
+    };
+    r.headers_mut()
+        .insert("x-tags", header::HeaderValue::from_str(&tags).unwrap());
+    Ok(())
+}
+
+#[handler]
+async fn index() -> &'static str {
+    "index"
+}
+
+#[handler]
+async fn users() -> &'static str {
+    "users"
+}
+
+#[handler]
+async fn stats() -> &'static str {
+    "stats"
+}
+
+async fn get(uri: &str, role: Option<&str>) -> (StatusCode, Option<String>, String) {
+    let mut req = hyper::Request::get(uri);
+    if let Some(role) = role {
+        req = req.header("x-role", role);
+    }
+    let resp = hyper::Client::new()
+        .request(req.body(hyper::Body::empty()).unwrap())
+        .await
+        .expect("request failed");
+    let status = resp.status();
+    let tags = resp
+        .headers()
+        .get("x-tags")
+        .map(|v| v.to_str().unwrap().to_string());
+    (status, tags, text(resp).await)
+}
+
+#[tokio::test]
+async fn groups() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            request: [],
+            response: [tag("global")]
+        },
+        handlers: [{
+            route: "/",
+            method: Method::GET,
+            handler: index
+        },{
+            group: "/api/v1",
+            middleware: {
+                request: [authorize("user")],
+                response: [tag("api")]
+            },
+            handlers: [{
+                route: "/users",
+                method: Method::GET,
+                handler: users
+            },{
+                group: "/admin",
+                middleware: {
+                    response: [tag("admin")]
+                },
+                handlers: [{
+                    route: "/",
+                    method: Method::GET,
+                    handler: stats
+                }]
+            }]
+        }]
+    });
+    let server = app.bind().expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    assert_eq!(
+        get(&format!("{}/", base), None).await,
+        (
+            StatusCode::OK,
+            Some("global".to_string()),
+            "index".to_string()
+        )
+    );
+    assert_eq!(
+        get(&format!("{}/api/v1/users", base), Some("user")).await,
+        (
+            StatusCode::OK,
+            Some("api,global".to_string()),
+            "users".to_string()
+        )
+    );
+    assert_eq!(
+        get(&format!("{}/api/v1/admin", base), Some("user")).await,
+        (
+            StatusCode::OK,
+            Some("admin,api,global".to_string()),
+            "stats".to_string()
+        )
+    );
+    assert_eq!(
+        get(&format!("{}/api/v1/users", base), None).await,
+        (StatusCode::FORBIDDEN, None, "forbidden".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/users", base), Some("user")).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
@@ -0,0 +1,146 @@
+use darpi::job::FutureJob;
+use darpi::response::ResponderError;
+use darpi::test::{
+    mock_module, request_job, request_middleware, response_job, response_middleware, run_job,
+    RequestBuilder, ResponseBuilder,
+};
+use darpi::{header, job_factory, middleware, Body, RequestParts, Response, StatusCode};
+use shaku::{module, Component, Interface};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+pub trait TokenStore: Interface {
+    fn user(&self, token: &str) -> Option<String>;
+}
+
+#[derive(Component)]
+#[shaku(interface = TokenStore)]
+pub struct NoTokens;
+
+impl TokenStore for NoTokens {
+    fn user(&self, _token: &str) -> Option<String> {
+        None
+    }
+}
+
+struct MockTokens;
+
+impl TokenStore for MockTokens {
+    fn user(&self, token: &str) -> Option<String> {
+        match token {
+            "secret" => Some("petar".to_string()),
+            _ => None,
+        }
+    }
+}
+
+module! {
+    Container {
+        components = [NoTokens],
+        providers = [],
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Unauthorized;
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unauthorized")
+    }
+}
+
+impl ResponderError for Unauthorized {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+#[middleware(Request)]
+async fn authenticate(
+    #[request_parts] rp: &mut RequestParts,
+    #[inject] tokens: Arc<dyn TokenStore>,
+) -> Result<String, Unauthorized> {
+    let token = rp
+        .headers
+        .get("x-token")
+        .and_then(|t| t.to_str().ok())
+        .ok_or(Unauthorized)?;
+    let user = tokens.user(token).ok_or(Unauthorized)?;
+    rp.headers.remove("x-token");
+    Ok(user)
+}
+
+#[middleware(Response)]
+async fn tag(
+    #[response] r: &mut Response<Body>,
+    #[handler] value: &'static str,
+) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-tag", header::HeaderValue::from_static(value));
+    Ok(())
+}
+
+#[job_factory(Request)]
+async fn audit(#[request_parts] rp: &RequestParts) -> FutureJob<()> {
+    let path = rp.uri.path().to_string();
+    async move {
+        assert_eq!(path, "/admin");
+    }
+    .into()
+}
+
+#[job_factory(Response)]
+async fn count_errors(#[response] r: &Response<Body>) -> FutureJob<()> {
+    let status = r.status();
+    async move {
+        assert!(status.is_server_error());
+    }
+    .into()
+}
+
+#[tokio::test]
+async fn middleware() {
+    let module = mock_module::<Container>(|b| {
+        b.with_component_override::<dyn TokenStore>(Box::new(MockTokens))
+    });
+
+    let mut req = RequestBuilder::get("/admin")
+        .header("x-token", "secret")
+        .build();
+    let user = request_middleware::<authenticate, _>(&mut req, module.clone(), ()).await;
+    assert_eq!(user, Ok("petar".to_string()));
+    // changes to the request are kept
+    assert!(req.headers().get("x-token").is_none());
+
+    let mut req = RequestBuilder::get("/admin")
+        .header("x-token", "wrong")
+        .build();
+    let user = request_middleware::<authenticate, _>(&mut req, module, ()).await;
+    assert_eq!(user, Err(Unauthorized));
+
+    // without the mock, no token is valid
+    let mut req = RequestBuilder::get("/admin")
+        .header("x-token", "secret")
+        .build();
+    let module = Arc::new(Container::builder().build());
+    let user = request_middleware::<authenticate, _>(&mut req, module, ()).await;
+    assert_eq!(user, Err(Unauthorized));
+
+    let mut resp = ResponseBuilder::ok().body("hello").build();
+    response_middleware::<tag, _>(&mut resp, Arc::new(()), "tagged")
+        .await
+        .unwrap();
+    assert_eq!(resp.headers().get("x-tag").unwrap(), "tagged");
+}
+
+#[tokio::test]
+async fn jobs() {
+    let mut req = RequestBuilder::get("/admin").build();
+    let job = request_job::<audit, _>(&mut req, Arc::new(()), ()).await;
+    run_job(job).await;
+
+    let resp = ResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR).build();
+    let job = response_job::<count_errors, _>(&resp, Arc::new(()), ()).await;
+    run_job(job).await;
+}
@@ -0,0 +1,94 @@
+use darpi::header::HeaderValue;
+use darpi::request::FromHeader;
+use darpi::test::{RequestBuilder, TestClient};
+use darpi::{app, handler, Method, RequestParts, StatusCode};
+use darpi_headers::UserAgent;
+use std::num::ParseIntError;
+
+/// A user-defined typed header
+pub struct TenantId(u32);
+
+impl FromHeader for TenantId {
+    type Error = ParseIntError;
+
+    fn from_header(value: &HeaderValue) -> Result<Self, Self::Error> {
+        String::from_utf8_lossy(value.as_bytes())
+            .parse()
+            .map(TenantId)
+    }
+}
+
+#[handler]
+async fn agent(#[header] ua: UserAgent) -> String {
+    ua.to_string()
+}
+
+#[handler]
+async fn tenant(#[header("x-tenant")] t: Option<TenantId>) -> String {
+    match t {
+        Some(t) => format!("tenant {}", t.0),
+        None => "no tenant".to_string(),
+    }
+}
+
+#[handler]
+async fn request_id(
+    #[request_parts] rp: &RequestParts,
+    #[header("x-request-id")] id: String,
+) -> String {
+    format!("{} {}", rp.uri.path(), id)
+}
+
+#[tokio::test]
+async fn typed_headers() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/agent",
+            method: Method::GET,
+            handler: agent
+        },{
+            route: "/tenant",
+            method: Method::GET,
+            handler: tenant
+        },{
+            route: "/request_id",
+            method: Method::GET,
+            handler: request_id
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let req = RequestBuilder::get("/agent")
+        .header("user-agent", "curl/7.64.1")
+        .build();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.text().await, "curl/7.64.1");
+
+    let resp = client.get("/agent").await;
+    resp.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(resp.text().await, "missing header `user-agent`");
+
+    // optional headers may be missing, but not malformed
+    let req = RequestBuilder::get("/tenant")
+        .header("x-tenant", "42")
+        .build();
+    assert_eq!(client.send(req).await.text().await, "tenant 42");
+    assert_eq!(client.get("/tenant").await.text().await, "no tenant");
+
+    let req = RequestBuilder::get("/tenant")
+        .header("x-tenant", "acme")
+        .build();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(
+        resp.text().await,
+        "malformed header `x-tenant`: invalid digit found in string"
+    );
+
+    let req = RequestBuilder::get("/request_id")
+        .header("x-request-id", "abc")
+        .build();
+    assert_eq!(client.send(req).await.text().await, "/request_id abc");
+}
@@ -1,7 +1,7 @@
 use darpi::job::{CpuJob, FutureJob, IOBlockingJob};
 use darpi::{
-    app, from_path, handler, job_factory, logger::DefaultFormat, middleware, Body, Json, Method,
-    Query, RequestParts, Response,
+    app, from_path, handler, hyper, job_factory, logger::DefaultFormat, middleware, Body, Json,
+    Method, Query, RequestParts, Response, StatusCode,
 };
 use darpi_middleware::{log_request, log_response};
 use env_logger;
@@ -149,11 +149,11 @@ async fn do_something123(
 //RUST_LOG=darpi=info cargo test --test job -- --nocapture
 //#[tokio::test]
 #[tokio::test]
-async fn main() -> Result<(), darpi::Error> {
+async fn main() -> Result<(), darpi::server::Error> {
     env_logger::builder().is_test(true).try_init().unwrap();
 
-    app!({
-        address: "127.0.0.1:3000",
+    let server = app!({
+        address: "127.0.0.1:0",
         container: {
             factory: make_container(),
             type: Container
@@ -172,6 +172,17 @@ async fn main() -> Result<(), darpi::Error> {
             handler: hello_world
         }]
     })
-    .run()
-    .await
+    .bind()?;
+    let uri = format!("http://{}/hello_world", server.local_addr());
+    // the server ends with the runtime of the test
+    tokio::spawn(server);
+
+    let resp = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .expect("request failed");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(body, "hello world");
+    Ok(())
 }
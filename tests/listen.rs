@@ -0,0 +1,130 @@
+mod common;
+
+use common::get_raw;
+use darpi::listen::Address;
+use darpi::{app, handler, Method};
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpStream, UnixStream};
+
+#[handler]
+async fn hello() -> &'static str {
+    "hello"
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("darpi-{}-{}.sock", name, std::process::id()))
+}
+
+async fn get_unix(path: &Path) -> String {
+    get_raw(UnixStream::connect(path).await.unwrap(), "/hello").await
+}
+
+async fn get_tcp(address: std::net::SocketAddr) -> String {
+    get_raw(TcpStream::connect(address).await.unwrap(), "/hello").await
+}
+
+#[test]
+fn parse_address() {
+    assert_eq!(
+        "127.0.0.1:3000".parse::<Address>().unwrap(),
+        Address::Tcp("127.0.0.1:3000".parse().unwrap())
+    );
+    assert_eq!(
+        "unix:/run/app.sock".parse::<Address>().unwrap(),
+        Address::Unix("/run/app.sock".into())
+    );
+    assert_eq!("systemd".parse::<Address>().unwrap(), Address::Systemd);
+
+    for invalid in &["localhost", "unix:", "fd:3", ""] {
+        let err = invalid.parse::<Address>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("invalid server address: `{}`", invalid)
+        );
+    }
+}
+
+#[tokio::test]
+async fn unix_socket() {
+    let path = socket_path("unix");
+    // a stale socket file is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    let address = format!("unix:{}", path.display());
+
+    let server = app!({
+        address: address,
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    })
+    .bind()
+    .expect("could not bind");
+    assert_eq!(server.local_addrs(), &[Address::Unix(path.clone())]);
+    tokio::spawn(server);
+
+    let resp = get_unix(&path).await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert!(resp.ends_with("hello"), "{}", resp);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn multiple_listeners() {
+    let path = socket_path("multiple");
+    let unix = format!("unix:{}", path.display());
+
+    let server = app!({
+        address: ["127.0.0.1:0", unix],
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    })
+    .bind()
+    .expect("could not bind");
+    let tcp = server.local_addr();
+    assert_eq!(
+        server.local_addrs(),
+        &[Address::Tcp(tcp), Address::Unix(path.clone())]
+    );
+    tokio::spawn(server);
+
+    for resp in [get_tcp(tcp).await, get_unix(&path).await] {
+        assert!(resp.ends_with("hello"), "{}", resp);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn live_unix_socket() {
+    let path = socket_path("live");
+    let address = format!("unix:{}", path.display());
+    let bind = || {
+        app!({
+            address: address.clone(),
+            handlers: [{
+                route: "/hello",
+                method: Method::GET,
+                handler: hello
+            }]
+        })
+        .bind()
+    };
+
+    let server = bind().expect("could not bind");
+    // the socket of a running server is not taken over
+    let err = bind().err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "could not listen on `{}`: another server is listening on the socket",
+            address
+        )
+    );
+    tokio::spawn(server);
+    assert!(get_unix(&path).await.ends_with("hello"));
+    std::fs::remove_file(&path).unwrap();
+}
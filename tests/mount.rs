@@ -0,0 +1,254 @@
+mod common;
+
+use common::text;
+use darpi::{
+    app, handler, header, hyper, middleware, Body, Method, RequestParts, Response, StatusCode,
+};
+use shaku::{module, Component, Interface};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+fn make_container() -> Container {
+    Container::builder().build()
+}
+
+pub trait Signature: Interface {
+    fn sign(&self) -> &'static str;
+}
+
+#[derive(Component)]
+#[shaku(interface = Signature)]
+struct SignatureImpl;
+
+impl Signature for SignatureImpl {
+    fn sign(&self) -> &'static str {
+        "darpi"
+    }
+}
+
+module! {
+    Container {
+        components = [SignatureImpl],
+        providers = [],
+    }
+}
+
+// needs a container with a `Signature`
+#[middleware(Response)]
+async fn sign(
+    #[response] r: &mut Response<Body>,
+    #[inject] signature: Arc<dyn Signature>,
+) -> Result<(), Infallible> {
+    r.headers_mut().insert(
+        "x-signed",
+        header::HeaderValue::from_static(signature.sign()),
+    );
+    Ok(())
+}
+
+// generic over its argument
+#[middleware(Request)]
+async fn label(
+    #[request_parts] _rp: &RequestParts,
+    #[handler] value: impl AsRef<str> + Send + Sync + 'static,
+) -> Result<String, Infallible> {
+    Ok(value.as_ref().to_string())
+}
+
+#[middleware(Response)]
+async fn tag(
+    #[response] r: &mut Response<Body>,
+    #[handler] value: &'static str,
+) -> Result<(), Infallible> {
+    let tags = match r.headers().get("x-tags") {
+        Some(t) => format!("{},{}", t.to_str().unwrap(), value),
+        None => value.to_string(),
+    };
+    r.headers_mut()
+        .insert("x-tags", header::HeaderValue::from_str(&tags).unwrap());
+    Ok(())
+}
+
+mod billing {
+    use darpi::{from_path, handler, routes, Method};
+    use serde::{Deserialize, Serialize};
+
+    #[from_path]
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct InvoiceId {
+        id: u64,
+    }
+
+    #[handler]
+    async fn index() -> &'static str {
+        "billing"
+    }
+
+    #[handler]
+    async fn invoice(#[path] p: InvoiceId) -> String {
+        format!("invoice {}", p.id)
+    }
+
+    #[handler]
+    async fn refund(#[path] p: InvoiceId) -> String {
+        format!("refund {}", p.id)
+    }
+
+    routes!({
+        name: Billing,
+        middleware: {
+            request: [super::label("billing")],
+            response: [super::tag("billing"), super::sign]
+        },
+        handlers: [{
+            route: "/",
+            method: Method::GET,
+            handler: index
+        },{
+            route: "/invoices/{id:u64}",
+            method: Method::GET,
+            handler: invoice
+        }]
+    });
+
+    // a second set of routes in the same module
+    routes!({
+        name: Refunds,
+        handlers: [{
+            route: "/{id:u64}",
+            method: Method::POST,
+            handler: refund
+        }]
+    });
+}
+
+mod admin {
+    use darpi::{handler, routes, Method};
+
+    #[handler({
+        container: super::Container
+    })]
+    async fn index() -> &'static str {
+        "admin"
+    }
+
+    routes!({
+        name: Admin,
+        container: super::Container,
+        handlers: [{
+            route: "/",
+            method: Method::POST,
+            handler: index
+        }]
+    });
+}
+
+#[handler]
+async fn index() -> &'static str {
+    "index"
+}
+
+async fn request(method: Method, uri: &str) -> (StatusCode, Option<String>, String) {
+    let req = hyper::Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = hyper::Client::new()
+        .request(req)
+        .await
+        .expect("request failed");
+    let status = resp.status();
+    let tags = resp
+        .headers()
+        .get("x-tags")
+        .map(|v| v.to_str().unwrap().to_string());
+    (status, tags, text(resp).await)
+}
+
+#[tokio::test]
+async fn mount() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        middleware: {
+            response: [tag("global")]
+        },
+        handlers: [{
+            route: "/",
+            method: Method::GET,
+            handler: index
+        }],
+        mount: [
+            ("/billing", billing::Billing),
+            ("/refunds", billing::Refunds),
+            ("/admin", admin::Admin)
+        ]
+    });
+    let server = app.bind().expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    assert_eq!(
+        request(Method::GET, &format!("{}/", base)).await,
+        (
+            StatusCode::OK,
+            Some("global".to_string()),
+            "index".to_string()
+        )
+    );
+    assert_eq!(
+        request(Method::GET, &format!("{}/billing", base)).await,
+        (
+            StatusCode::OK,
+            Some("billing,global".to_string()),
+            "billing".to_string()
+        )
+    );
+    let resp = hyper::Client::new()
+        .get(format!("{}/billing", base).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["x-signed"], "darpi");
+    assert_eq!(
+        request(Method::GET, &format!("{}/billing/invoices/7", base)).await,
+        (
+            StatusCode::OK,
+            Some("billing,global".to_string()),
+            "invoice 7".to_string()
+        )
+    );
+    assert_eq!(
+        request(Method::GET, &format!("{}/billing/invoices/x", base))
+            .await
+            .0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        request(Method::GET, &format!("{}/billingx", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        request(Method::POST, &format!("{}/refunds/7", base)).await,
+        (
+            StatusCode::OK,
+            Some("global".to_string()),
+            "refund 7".to_string()
+        )
+    );
+    assert_eq!(
+        request(Method::POST, &format!("{}/admin", base)).await,
+        (
+            StatusCode::OK,
+            Some("global".to_string()),
+            "admin".to_string()
+        )
+    );
+    assert_eq!(
+        request(Method::GET, &format!("{}/admin", base)).await.0,
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+}
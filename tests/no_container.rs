@@ -0,0 +1,89 @@
+mod common;
+
+use common::Counter;
+use darpi::job::FutureJob;
+use darpi::once_cell::sync::Lazy;
+use darpi::test::TestClient;
+use darpi::{
+    app, from_path, handler, header, job_factory, middleware, Body, Json, Method, RequestParts,
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Name {
+    name: String,
+}
+
+#[middleware(Request)]
+async fn greeting(#[handler] word: &'static str) -> Result<String, Infallible> {
+    Ok(word.to_string())
+}
+
+#[middleware(Response)]
+async fn served_by(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-served-by", header::HeaderValue::from_static("darpi"));
+    Ok(())
+}
+
+static REQUESTS: Lazy<Counter> = Lazy::new(Counter::new);
+
+#[job_factory(Request)]
+async fn count(#[request_parts] _rp: &RequestParts) -> FutureJob<()> {
+    async {
+        REQUESTS.add();
+    }
+    .into()
+}
+
+#[handler({
+    middleware: {
+        request: [greeting("hello")]
+    },
+    jobs: {
+        request: [count]
+    }
+})]
+async fn greet(#[path] p: Name, #[middleware::request(0)] word: String) -> String {
+    format!("{} {}", word, p.name)
+}
+
+#[handler]
+async fn echo(#[body] body: Json<Vec<u32>>) -> Json<Vec<u32>> {
+    body
+}
+
+#[tokio::test]
+async fn without_container() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            response: [served_by]
+        },
+        handlers: [{
+            route: "/hello/{name}",
+            method: Method::GET,
+            handler: greet
+        },{
+            route: "/echo",
+            method: Method::POST,
+            handler: echo
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.get("/hello/john").await;
+    resp.assert_status(StatusCode::OK)
+        .assert_header("x-served-by", "darpi");
+    assert_eq!(resp.text().await, "hello john");
+
+    let resp = client.post_json("/echo", &vec![1, 2, 3]).await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.json::<Vec<u32>>().await, vec![1, 2, 3]);
+
+    // request jobs run in the background
+    assert_eq!(REQUESTS.reached(1).await, 1);
+}
@@ -0,0 +1,124 @@
+use darpi::panic::{PanicHandler, PanicInfo};
+use darpi::test::TestClient;
+use darpi::{app, handler, header, middleware, Body, Method, Response, StatusCode};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[handler]
+async fn fine() -> &'static str {
+    "fine"
+}
+
+#[handler]
+async fn broken() -> &'static str {
+    panic!("broken handler")
+}
+
+#[middleware(Request)]
+async fn explode(#[handler] code: u16) -> Result<(), Infallible> {
+    panic!("exploded with {}", code)
+}
+
+#[handler({
+    middleware: {
+        request: [explode(7)]
+    }
+})]
+async fn guarded() -> &'static str {
+    "unreachable"
+}
+
+#[middleware(Response)]
+async fn served_by(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-served-by", header::HeaderValue::from_static("darpi"));
+    Ok(())
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl PanicHandler for Recorder {
+    fn report(&self, info: &PanicInfo<'_>) {
+        self.0.lock().unwrap().push(format!(
+            "{} {} {}",
+            info.method(),
+            info.route(),
+            info.message()
+        ));
+    }
+
+    fn respond(&self, _info: &PanicInfo<'_>) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from("something went wrong"))
+            .unwrap()
+    }
+}
+
+static HOOK_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+#[tokio::test]
+async fn panics() {
+    let make_app = || {
+        app!({
+            address: "127.0.0.1:0",
+            middleware: {
+                response: [served_by]
+            },
+            handlers: [{
+                route: "/fine",
+                method: Method::GET,
+                handler: fine
+            },{
+                route: "/broken",
+                method: Method::POST,
+                handler: broken
+            },{
+                route: "/guarded",
+                method: Method::GET,
+                handler: guarded
+            }]
+        })
+    };
+
+    let recorder = Recorder::default();
+    let client = TestClient::new(make_app().panic_handler(recorder.clone()).service());
+
+    // a panicking handler still goes through the response middleware
+    let resp = client.request(Method::POST, "/broken").await;
+    resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR)
+        .assert_header("x-served-by", "darpi");
+    assert_eq!(resp.text().await, "something went wrong");
+
+    let resp = client.get("/guarded").await;
+    resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.text().await, "something went wrong");
+
+    // other requests are not affected
+    client.get("/fine").await.assert_status(StatusCode::OK);
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            "POST /broken broken handler".to_string(),
+            "GET /guarded exploded with 7".to_string()
+        ]
+    );
+
+    // the default handler answers with an empty 500
+    let client = TestClient::new(make_app().service());
+    let resp = client.request(Method::POST, "/broken").await;
+    resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.text().await, "");
+
+    // binding the server doesn't replace the process-wide panic hook
+    std::panic::set_hook(Box::new(|_| {
+        HOOK_CALLS.fetch_add(1, Ordering::SeqCst);
+    }));
+    let _server = make_app().bind().expect("could not bind");
+    client.request(Method::POST, "/broken").await;
+    let _ = std::panic::take_hook();
+    assert_eq!(HOOK_CALLS.load(Ordering::SeqCst), 1);
+}
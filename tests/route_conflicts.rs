@@ -0,0 +1,6 @@
+#[test]
+fn route_conflicts() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/route_conflicts/fail/*.rs");
+    t.pass("tests/route_conflicts/pass/*.rs");
+}
@@ -0,0 +1,32 @@
+use darpi::{app, handler, routes, Method};
+
+#[handler]
+async fn first() -> &'static str {
+    "first"
+}
+
+#[handler]
+async fn second() -> &'static str {
+    "second"
+}
+
+routes!({
+    name: Mounted,
+    handlers: [{
+        route: "/{id}",
+        method: Method::GET,
+        handler: second
+    }]
+});
+
+fn main() {
+    app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/billing/{id}",
+            method: Method::GET,
+            handler: first
+        }],
+        mount: [("/billing", Mounted)]
+    });
+}
@@ -0,0 +1,26 @@
+use darpi::{app, handler, Method};
+
+#[handler]
+async fn first() -> &'static str {
+    "first"
+}
+
+#[handler]
+async fn second() -> &'static str {
+    "second"
+}
+
+fn main() {
+    app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/user/{id:u64}",
+            method: Method::GET,
+            handler: first
+        },{
+            route: "/user/{slug:[a-z-]+}",
+            method: Method::GET,
+            handler: second
+        }]
+    });
+}
@@ -0,0 +1,559 @@
+mod common;
+
+use common::text;
+use darpi::response::ResponderError;
+use darpi::test::TestClient;
+use darpi::{
+    app, from_path, handler, header, hyper, middleware, Body, Method, RequestParts, Response,
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserId {
+    id: String,
+}
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserArticle {
+    id: String,
+    article: String,
+}
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Asset {
+    path: String,
+}
+
+#[handler]
+async fn asset(#[path] p: Asset) -> String {
+    format!("asset {}", p.path)
+}
+
+#[handler]
+async fn meta() -> &'static str {
+    "meta"
+}
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PostId {
+    id: u64,
+}
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PostSlug {
+    slug: String,
+}
+
+#[handler]
+async fn post_by_id(#[path] p: PostId) -> String {
+    format!("post id {}", p.id)
+}
+
+#[handler]
+async fn post_by_slug(#[path] p: PostSlug) -> String {
+    format!("post slug {}", p.slug)
+}
+
+mod api {
+    pub mod users {
+        use darpi::handler;
+
+        #[handler]
+        pub async fn list() -> &'static str {
+            "api users"
+        }
+    }
+}
+
+mod admin {
+    use darpi::handler;
+
+    #[handler]
+    pub async fn list() -> &'static str {
+        "admin users"
+    }
+}
+
+#[handler]
+async fn about() -> &'static str {
+    "about"
+}
+
+#[derive(Debug)]
+pub struct Teapot;
+
+impl std::fmt::Display for Teapot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "teapot")
+    }
+}
+
+impl ResponderError for Teapot {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::IM_A_TEAPOT
+    }
+}
+
+#[handler]
+async fn teapot() -> Result<&'static str, Teapot> {
+    Err(Teapot)
+}
+
+#[handler]
+async fn index() -> &'static str {
+    "index"
+}
+
+#[handler]
+async fn me() -> &'static str {
+    "me"
+}
+
+#[handler]
+async fn user(#[path] p: UserId) -> String {
+    format!("user {}", p.id)
+}
+
+#[handler]
+async fn delete_user(#[path] p: UserId) -> String {
+    format!("deleted {}", p.id)
+}
+
+#[handler]
+async fn article(#[path] p: UserArticle) -> String {
+    format!("user {} article {}", p.id, p.article)
+}
+
+#[middleware(Response)]
+async fn seen(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-seen", header::HeaderValue::from_static("true"));
+    Ok(())
+}
+
+#[middleware(Request)]
+async fn deny(#[request_parts] rp: &RequestParts) -> Result<(), Teapot> {
+    match rp.headers.get("x-deny") {
+        Some(_) => Err(Teapot),
+        None => Ok(()),
+    }
+}
+
+#[middleware(Response)]
+async fn stream(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    let chunks: Vec<Result<&'static str, Infallible>> = vec![Ok("stre"), Ok("amed")];
+    *r.body_mut() = Body::wrap_stream(darpi::futures::stream::iter(chunks));
+    Ok(())
+}
+
+#[middleware(Response)]
+async fn sized(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert(header::CONTENT_LENGTH, header::HeaderValue::from(8));
+    Ok(())
+}
+
+#[handler({
+    middleware: {
+        response: [stream]
+    }
+})]
+async fn streamed() -> &'static str {
+    ""
+}
+
+#[handler({
+    middleware: {
+        response: [stream, sized]
+    }
+})]
+async fn streamed_sized() -> &'static str {
+    ""
+}
+
+async fn get(uri: &str) -> (StatusCode, String) {
+    let resp = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .expect("request failed");
+    let status = resp.status();
+    (status, text(resp).await)
+}
+
+async fn request(method: Method, uri: &str) -> (StatusCode, Option<String>) {
+    let req = hyper::Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let resp = hyper::Client::new()
+        .request(req)
+        .await
+        .expect("request failed");
+    let allow = resp
+        .headers()
+        .get(header::ALLOW)
+        .map(|v| v.to_str().unwrap().to_string());
+    (resp.status(), allow)
+}
+
+/// Serves the whole match tree and returns its base url
+fn serve_tree() -> String {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/",
+            method: Method::GET,
+            handler: index
+        },{
+            route: "/user/{id}",
+            method: Method::GET,
+            handler: user
+        },{
+            route: "/user/{id}",
+            method: Method::DELETE,
+            handler: delete_user
+        },{
+            route: "/user/me",
+            method: Method::GET,
+            handler: me
+        },{
+            route: "/user/{id}/article/{article}",
+            method: Method::GET,
+            handler: article
+        },{
+            route: "/static/{*path}",
+            method: Method::GET,
+            handler: asset
+        },{
+            route: "/files/*/meta",
+            method: Method::GET,
+            handler: meta
+        },{
+            route: "/index",
+            method: Method::GET,
+            handler: index
+        },{
+            route: "/api/users",
+            method: Method::GET,
+            handler: api::users::list
+        },{
+            route: "/admin/users",
+            method: Method::GET,
+            handler: crate::admin::list
+        },{
+            route: "/about",
+            method: Method::GET,
+            handler: about
+        },{
+            route: "/about",
+            method: Method::HEAD,
+            handler: teapot
+        },{
+            route: "/about",
+            method: Method::OPTIONS,
+            handler: teapot
+        },{
+            route: "/post/{id:u64}",
+            method: Method::GET,
+            handler: post_by_id
+        },{
+            route: "/post/{slug:[a-z][a-z0-9-]*}",
+            method: Method::GET,
+            handler: post_by_slug
+        }]
+    });
+    let server = app.bind().expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    base
+}
+
+async fn head(uri: &str) -> hyper::Response<hyper::Body> {
+    hyper::Client::new()
+        .request(
+            hyper::Request::head(uri)
+                .body(hyper::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .expect("request failed")
+}
+
+#[tokio::test]
+async fn static_routes() {
+    let base = serve_tree();
+
+    assert_eq!(
+        get(&format!("{}/", base)).await,
+        (StatusCode::OK, "index".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/index", base)).await,
+        (StatusCode::OK, "index".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/api/users", base)).await,
+        (StatusCode::OK, "api users".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/admin/users", base)).await,
+        (StatusCode::OK, "admin users".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/nope", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn path_arguments() {
+    let base = serve_tree();
+
+    assert_eq!(
+        get(&format!("{}/user/me", base)).await,
+        (StatusCode::OK, "me".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/user/1", base)).await,
+        (StatusCode::OK, "user 1".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/user/1/article/2", base)).await,
+        (StatusCode::OK, "user 1 article 2".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/user", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&format!("{}/user/", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&format!("{}/user/1/article", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn wildcards() {
+    let base = serve_tree();
+
+    assert_eq!(
+        get(&format!("{}/static/css/main.css", base)).await,
+        (StatusCode::OK, "asset css/main.css".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/static/", base)).await,
+        (StatusCode::OK, "asset ".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/static", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        get(&format!("{}/files/1/meta", base)).await,
+        (StatusCode::OK, "meta".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/files/1/2/meta", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn constrained_arguments() {
+    let base = serve_tree();
+
+    assert_eq!(
+        get(&format!("{}/post/42", base)).await,
+        (StatusCode::OK, "post id 42".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/post/hello-world", base)).await,
+        (StatusCode::OK, "post slug hello-world".to_string())
+    );
+    assert_eq!(
+        get(&format!("{}/post/Hello", base)).await.0,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn allowed_methods() {
+    let base = serve_tree();
+
+    assert_eq!(
+        request(Method::DELETE, &format!("{}/user/me", base)).await,
+        (StatusCode::OK, None)
+    );
+    assert_eq!(
+        request(Method::POST, &format!("{}/user/1", base)).await,
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            Some("GET, HEAD, DELETE, OPTIONS".to_string())
+        )
+    );
+    assert_eq!(
+        request(Method::POST, &format!("{}/static/a/b", base)).await,
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            Some("GET, HEAD, OPTIONS".to_string())
+        )
+    );
+    assert_eq!(
+        request(Method::OPTIONS, &format!("{}/user/me", base)).await,
+        (
+            StatusCode::NO_CONTENT,
+            Some("GET, HEAD, DELETE, OPTIONS".to_string())
+        )
+    );
+    assert_eq!(
+        request(Method::OPTIONS, &format!("{}/nope", base)).await,
+        (StatusCode::NOT_FOUND, None)
+    );
+    assert_eq!(
+        request(Method::POST, &format!("{}/nope", base)).await,
+        (StatusCode::NOT_FOUND, None)
+    );
+}
+
+#[tokio::test]
+async fn implicit_head() {
+    let base = serve_tree();
+
+    let resp = head(&format!("{}/user/1", base)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_LENGTH], "6");
+    assert!(hyper::body::to_bytes(resp.into_body())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn explicit_head_and_options() {
+    let base = serve_tree();
+
+    assert_eq!(
+        get(&format!("{}/about", base)).await,
+        (StatusCode::OK, "about".to_string())
+    );
+    assert_eq!(
+        request(Method::HEAD, &format!("{}/about", base)).await,
+        (StatusCode::IM_A_TEAPOT, None)
+    );
+    assert_eq!(
+        request(Method::OPTIONS, &format!("{}/about", base)).await,
+        (StatusCode::IM_A_TEAPOT, None)
+    );
+}
+
+#[tokio::test]
+async fn head_precedence() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/user/me",
+            method: Method::GET,
+            handler: me
+        },{
+            route: "/user/{id}",
+            method: Method::HEAD,
+            handler: teapot
+        }]
+    });
+    let server = app.bind().expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    // `/user/me` is more specific, so its `GET` answers `HEAD` too
+    let resp = head(&format!("{}/user/me", base)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_LENGTH], "2");
+    assert_eq!(
+        request(Method::HEAD, &format!("{}/user/1", base)).await,
+        (StatusCode::IM_A_TEAPOT, None)
+    );
+}
+
+#[tokio::test]
+async fn allowed_methods_through_response_middleware() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            response: [seen]
+        },
+        handlers: [{
+            route: "/user/{id}",
+            method: Method::GET,
+            handler: user
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.request(Method::POST, "/user/1").await;
+    resp.assert_status(StatusCode::METHOD_NOT_ALLOWED)
+        .assert_header(header::ALLOW, "GET, HEAD, OPTIONS")
+        .assert_header("x-seen", "true");
+
+    let resp = client.request(Method::OPTIONS, "/user/1").await;
+    resp.assert_status(StatusCode::NO_CONTENT)
+        .assert_header(header::ALLOW, "GET, HEAD, OPTIONS")
+        .assert_header("x-seen", "true");
+}
+
+#[tokio::test]
+async fn head_without_body() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            request: [deny]
+        },
+        handlers: [{
+            route: "/user/{id}",
+            method: Method::GET,
+            handler: user
+        },{
+            route: "/streamed",
+            method: Method::GET,
+            handler: streamed
+        },{
+            route: "/streamed/sized",
+            method: Method::GET,
+            handler: streamed_sized
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.request(Method::HEAD, "/user/1").await;
+    resp.assert_status(StatusCode::OK)
+        .assert_header(header::CONTENT_LENGTH, "6");
+    assert!(resp.text().await.is_empty());
+
+    // the early answer of a request middleware has no body either
+    let req = darpi::test::RequestBuilder::new(Method::HEAD, "/user/1")
+        .header("x-deny", "true")
+        .build();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::IM_A_TEAPOT);
+    assert!(resp.text().await.is_empty());
+
+    // a streamed body has no known length, unless one was set
+    let resp = client.request(Method::HEAD, "/streamed").await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.header(header::CONTENT_LENGTH), None);
+    assert!(resp.text().await.is_empty());
+
+    let resp = client.request(Method::HEAD, "/streamed/sized").await;
+    resp.assert_header(header::CONTENT_LENGTH, "8");
+    assert!(resp.text().await.is_empty());
+}
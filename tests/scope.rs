@@ -0,0 +1,248 @@
+use darpi::response::ResponderError;
+use darpi::scope::{RequestScoped, Scoped};
+use darpi::test::TestClient;
+use darpi::StatusCode;
+use darpi::{app, async_trait, handler, middleware, Body, Method, RequestParts, Response};
+use shaku::{module, Component, HasComponent, Interface};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub trait Database: Interface {
+    fn name(&self) -> String;
+}
+
+#[derive(Component)]
+#[shaku(interface = Database)]
+pub struct Postgres;
+
+impl Database for Postgres {
+    fn name(&self) -> String {
+        "postgres".to_string()
+    }
+}
+
+module! {
+    Container {
+        components = [Postgres],
+        providers = [],
+    }
+}
+
+fn make_container() -> Container {
+    Container::builder().build()
+}
+
+static EVENTS: Mutex<Vec<String>> = Mutex::new(vec![]);
+static BEGUN: AtomicUsize = AtomicUsize::new(0);
+
+fn record(event: String) {
+    EVENTS.lock().unwrap().push(event);
+}
+
+fn events() -> Vec<String> {
+    std::mem::take(&mut *EVENTS.lock().unwrap())
+}
+
+#[derive(Debug)]
+pub struct MissingTenant;
+
+impl std::fmt::Display for MissingTenant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "missing tenant")
+    }
+}
+
+impl ResponderError for MissingTenant {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// A transaction of the tenant of the request
+pub struct Transaction {
+    id: usize,
+    tenant: String,
+}
+
+#[async_trait]
+impl RequestScoped<Container> for Transaction {
+    type Error = MissingTenant;
+
+    async fn provide(
+        parts: &RequestParts,
+        container: Arc<Container>,
+    ) -> Result<Self, MissingTenant> {
+        let tenant = parts
+            .headers
+            .get("x-tenant")
+            .and_then(|t| t.to_str().ok())
+            .ok_or(MissingTenant)?
+            .to_string();
+        let db: Arc<dyn Database> = container.resolve();
+        let id = BEGUN.fetch_add(1, Ordering::SeqCst);
+        record(format!("begin {} on {}", tenant, db.name()));
+        Ok(Self { id, tenant })
+    }
+
+    async fn finish(self, response: &Response<Body>) {
+        if response.status().is_success() {
+            record(format!("commit {}", self.tenant));
+        } else {
+            record(format!("rollback {}", self.tenant));
+        }
+    }
+}
+
+#[middleware(Request)]
+async fn audit(#[inject] tx: Scoped<Transaction>) -> Result<(), MissingTenant> {
+    record(format!("audit {}", tx.id));
+    Ok(())
+}
+
+#[handler({
+    container: Container,
+    middleware: {
+        request: [audit]
+    }
+})]
+async fn create(#[inject] tx: Scoped<Transaction>) -> String {
+    format!("created by {} in {}", tx.tenant, tx.id)
+}
+
+#[handler({
+    container: Container
+})]
+async fn fail(#[inject] tx: Scoped<Transaction>) -> Result<String, String> {
+    Err(format!("failed in {}", tx.id))
+}
+
+#[handler]
+async fn untouched() -> &'static str {
+    "untouched"
+}
+
+/// The user of the request, provided without a container
+pub struct User(String);
+
+#[async_trait]
+impl<C: 'static + Sync + Send> RequestScoped<C> for User {
+    type Error = MissingTenant;
+
+    async fn provide(parts: &RequestParts, _container: Arc<C>) -> Result<Self, MissingTenant> {
+        let user = parts
+            .headers
+            .get("x-user")
+            .and_then(|u| u.to_str().ok())
+            .unwrap_or("anonymous");
+        Ok(User(user.to_string()))
+    }
+}
+
+#[handler]
+async fn whoami(#[inject] user: Scoped<User>) -> String {
+    user.0.clone()
+}
+
+/// A value which fails to finish
+pub struct Flaky;
+
+#[async_trait]
+impl<C: 'static + Sync + Send> RequestScoped<C> for Flaky {
+    type Error = MissingTenant;
+
+    async fn provide(_parts: &RequestParts, _container: Arc<C>) -> Result<Self, MissingTenant> {
+        Ok(Flaky)
+    }
+
+    async fn finish(self, _response: &Response<Body>) {
+        panic!("could not finish");
+    }
+}
+
+#[handler]
+async fn flaky(#[inject] _flaky: Scoped<Flaky>) -> &'static str {
+    "flaky"
+}
+
+#[tokio::test]
+async fn request_scoped() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        handlers: [{
+            route: "/create",
+            method: Method::POST,
+            handler: create
+        },{
+            route: "/fail",
+            method: Method::POST,
+            handler: fail
+        },{
+            route: "/untouched",
+            method: Method::GET,
+            handler: untouched
+        },{
+            route: "/whoami",
+            method: Method::GET,
+            handler: whoami
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    // the middleware and the handler share the value, which is committed with the response
+    let req = darpi::test::RequestBuilder::post("/create")
+        .header("x-tenant", "acme")
+        .build();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.text().await, "created by acme in 0");
+    assert_eq!(
+        events(),
+        vec!["begin acme on postgres", "audit 0", "commit acme"]
+    );
+
+    // every request gets its own value
+    let req = darpi::test::RequestBuilder::post("/fail")
+        .header("x-tenant", "acme")
+        .build();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(resp.text().await, "failed in 1");
+    assert_eq!(events(), vec!["begin acme on postgres", "rollback acme"]);
+
+    // values that can't be provided are answered with their error
+    let resp = client.request(Method::POST, "/create").await;
+    resp.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(resp.text().await, "missing tenant");
+
+    // nothing is built for requests that don't ask for it
+    client.get("/untouched").await.assert_status(StatusCode::OK);
+    assert!(events().is_empty());
+
+    let req = darpi::test::RequestBuilder::get("/whoami")
+        .header("x-user", "john")
+        .build();
+    assert_eq!(client.send(req).await.text().await, "john");
+}
+
+#[tokio::test]
+async fn finish_panics() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/flaky",
+            method: Method::GET,
+            handler: flaky
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    // finishing is part of the request, so its panics are answered by the panic handler
+    client
+        .get("/flaky")
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,216 @@
+use darpi::server::ServerConfig;
+use darpi::{app, handler, hyper, Method, StatusCode};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[handler]
+async fn hello() -> &'static str {
+    "hello"
+}
+
+#[handler]
+async fn slow() -> &'static str {
+    tokio::time::delay_for(Duration::from_millis(400)).await;
+    "slow"
+}
+
+/// Starts the app with `config` and returns its address
+fn start(config: ServerConfig) -> SocketAddr {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        },{
+            route: "/slow",
+            method: Method::GET,
+            handler: slow
+        }]
+    })
+    .server(config);
+
+    let server = app.bind().expect("could not bind");
+    let address = server.local_addr();
+    tokio::spawn(server);
+    address
+}
+
+/// Reads until the end of the stream and returns what was read
+async fn read_all(stream: &mut TcpStream) -> String {
+    let mut buf = vec![];
+    let _ = stream.read_to_end(&mut buf).await;
+    String::from_utf8(buf).unwrap()
+}
+
+#[tokio::test]
+async fn keep_alive_timeout() {
+    let address = start(ServerConfig::default().keep_alive_timeout(Duration::from_millis(200)));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    // the connection is closed once it is idle for the timeout, not while the handler runs
+    let start = Instant::now();
+    let resp = read_all(&mut stream).await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert!(resp.ends_with("slow"), "{}", resp);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(550), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn header_read_timeout() {
+    let address = start(ServerConfig::default().header_read_timeout(Duration::from_millis(200)));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nhost: local")
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    let resp = read_all(&mut stream).await;
+    assert!(!resp.contains("hello"), "{}", resp);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+/// Reads HTTP/2 frames until the acknowledgement of a `PING` and returns its payload
+async fn read_ping_ack(stream: &mut TcpStream) -> [u8; 8] {
+    loop {
+        let mut head = [0; 9];
+        stream
+            .read_exact(&mut head)
+            .await
+            .expect("connection closed");
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        // a `PING` frame with the `ACK` flag
+        if head[3] == 0x6 && head[4] & 0x1 == 0x1 {
+            let mut data = [0; 8];
+            data.copy_from_slice(&payload);
+            return data;
+        }
+    }
+}
+
+#[tokio::test]
+async fn header_read_timeout_http2() {
+    let address = start(ServerConfig::default().header_read_timeout(Duration::from_millis(200)));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    // the preface and empty `SETTINGS`
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+        .await
+        .unwrap();
+
+    // the connection stays open, while it is idle longer than the timeout
+    for i in 0..2u8 {
+        tokio::time::delay_for(Duration::from_millis(400)).await;
+        let mut ping = b"\0\0\x08\x06\0\0\0\0\0".to_vec();
+        ping.extend_from_slice(&[i; 8]);
+        stream.write_all(&ping).await.unwrap();
+        let ack = tokio::time::timeout(Duration::from_secs(1), read_ping_ack(&mut stream))
+            .await
+            .expect("the ping was not acknowledged");
+        assert_eq!(ack, [i; 8]);
+    }
+}
+
+#[tokio::test]
+async fn keep_alive_disabled() {
+    let address = start(ServerConfig::default().keep_alive(false).nodelay(true));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    let resp = tokio::time::timeout(Duration::from_secs(1), read_all(&mut stream))
+        .await
+        .expect("the connection was kept open");
+    assert!(resp.ends_with("hello"), "{}", resp);
+}
+
+#[tokio::test]
+async fn max_connections() {
+    let address = start(ServerConfig::default().max_connections(1));
+
+    let first = TcpStream::connect(address).await.unwrap();
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    let uri: hyper::Uri = format!("http://{}/hello", address).parse().unwrap();
+    let second = tokio::spawn(hyper::Client::new().get(uri));
+    tokio::time::delay_for(Duration::from_millis(200)).await;
+
+    // the second connection is only accepted, once the first one is closed
+    drop(first);
+    let resp = tokio::time::timeout(Duration::from_secs(1), second)
+        .await
+        .expect("the second connection was not accepted")
+        .unwrap()
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn http2_only() {
+    let address = start(ServerConfig::default().http2_only(true));
+
+    let client = hyper::Client::builder()
+        .http2_only(true)
+        .build_http::<hyper::Body>();
+    let resp = client
+        .get(format!("http://{}/hello", address).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.version(), hyper::Version::HTTP_2);
+
+    // HTTP/1 is not served
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let resp = read_all(&mut stream).await;
+    assert!(!resp.contains("hello"), "{}", resp);
+}
+
+#[tokio::test]
+async fn server_key() {
+    let server = app!({
+        address: "127.0.0.1:0",
+        server: {
+            keep_alive_timeout: Duration::from_secs(5),
+            max_connections: 10,
+            nodelay: true
+        },
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    })
+    .bind()
+    .expect("could not bind");
+    let uri = format!("http://{}/hello", server.local_addr());
+    tokio::spawn(server);
+
+    let resp = hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
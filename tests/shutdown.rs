@@ -0,0 +1,135 @@
+use darpi::job::IOBlockingJob;
+use darpi::{app, handler, hyper, Method, StatusCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static JOB_DONE: AtomicBool = AtomicBool::new(false);
+
+#[handler]
+async fn work() -> &'static str {
+    tokio::time::delay_for(Duration::from_millis(100)).await;
+    let job = || {
+        std::thread::sleep(Duration::from_millis(300));
+        JOB_DONE.store(true, Ordering::SeqCst);
+    };
+    darpi::spawn(IOBlockingJob::from(job))
+        .await
+        .expect("could not spawn job");
+    "done"
+}
+
+#[handler]
+async fn long_job() -> &'static str {
+    let job = || std::thread::sleep(Duration::from_secs(2));
+    darpi::spawn(IOBlockingJob::from(job))
+        .await
+        .expect("could not spawn job");
+    "started"
+}
+
+#[handler]
+async fn slow() -> &'static str {
+    tokio::time::delay_for(Duration::from_secs(10)).await;
+    "slow"
+}
+
+async fn get(uri: String) -> Result<StatusCode, hyper::Error> {
+    hyper::Client::new()
+        .get(uri.parse().unwrap())
+        .await
+        .map(|r| r.status())
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/work",
+            method: Method::GET,
+            handler: work
+        },{
+            route: "/slow",
+            method: Method::GET,
+            handler: slow
+        }]
+    })
+    .shutdown_timeout(Duration::from_millis(700));
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = app
+        .bind_with_shutdown(async {
+            let _ = rx.await;
+        })
+        .expect("could not bind");
+    let base = format!("http://{}", server.local_addr());
+    let server = tokio::spawn(server);
+
+    let work_req = tokio::spawn(get(format!("{}/work", base)));
+    let slow_req = tokio::spawn(get(format!("{}/slow", base)));
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    let start = Instant::now();
+    tx.send(()).unwrap();
+
+    // in-flight requests finish, new connections are refused
+    assert_eq!(work_req.await.unwrap().unwrap(), StatusCode::OK);
+    assert!(get(format!("{}/work", base)).await.is_err());
+
+    server.await.unwrap().unwrap();
+    // the background job finished before the server returned
+    assert!(JOB_DONE.load(Ordering::SeqCst));
+    // the slow request was cancelled at the deadline
+    assert_eq!(
+        slow_req.await.unwrap().unwrap(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn apps_wait_only_for_their_jobs() {
+    let busy = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/long_job",
+            method: Method::GET,
+            handler: long_job
+        }]
+    });
+    let idle = app!({
+        address: "127.0.0.1:0",
+        handlers: [{
+            route: "/work",
+            method: Method::GET,
+            handler: work
+        }]
+    })
+    .shutdown_timeout(Duration::from_secs(5));
+
+    let busy = busy.bind().expect("could not bind");
+    let busy_base = format!("http://{}", busy.local_addr());
+    // it ends with the runtime of the test
+    tokio::spawn(busy);
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let idle = idle
+        .bind_with_shutdown(async {
+            let _ = rx.await;
+        })
+        .expect("could not bind");
+    let idle = tokio::spawn(idle);
+
+    assert_eq!(
+        get(format!("{}/long_job", busy_base)).await.unwrap(),
+        StatusCode::OK
+    );
+
+    // the job of the other app is still running
+    let start = Instant::now();
+    tx.send(()).unwrap();
+    idle.await.unwrap().unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
@@ -0,0 +1,179 @@
+use darpi::state::{MissingState, State};
+use darpi::test::{request_middleware, RequestBuilder, TestClient};
+use darpi::{app, handler, middleware, Method, RequestParts, StatusCode};
+use shaku::{module, Component, Interface};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub trait Greeter: Interface {
+    fn greet(&self, name: &str) -> String;
+}
+
+#[derive(Component)]
+#[shaku(interface = Greeter)]
+pub struct HelloGreeter;
+
+impl Greeter for HelloGreeter {
+    fn greet(&self, name: &str) -> String {
+        format!("hello {}", name)
+    }
+}
+
+module! {
+    Container {
+        components = [HelloGreeter],
+        providers = [],
+    }
+}
+
+fn make_container() -> Container {
+    Container::builder().build()
+}
+
+pub struct Settings {
+    name: String,
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicUsize);
+
+#[middleware(Request)]
+async fn count(#[state] counter: Arc<Counter>) -> Result<usize, MissingState> {
+    Ok(counter.0.fetch_add(1, Ordering::SeqCst) + 1)
+}
+
+#[handler({
+    container: Container,
+    middleware: {
+        request: [count]
+    }
+})]
+async fn greet(
+    #[request_parts] _rp: &RequestParts,
+    #[state] settings: Arc<Settings>,
+    #[inject] greeter: Arc<dyn Greeter>,
+    #[middleware::request(0)] requests: usize,
+) -> String {
+    format!("{} #{}", greeter.greet(&settings.name), requests)
+}
+
+#[handler]
+async fn app_name(#[state] settings: Arc<Settings>) -> String {
+    settings.name.clone()
+}
+
+#[tokio::test]
+async fn state_alongside_container() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        state: {
+            Settings: Settings { name: "darpi".to_string() },
+            Counter: Counter::default(),
+        },
+        handlers: [{
+            route: "/greet",
+            method: Method::GET,
+            handler: greet
+        },{
+            route: "/name",
+            method: Method::GET,
+            handler: app_name
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    // the values are shared by every request
+    assert_eq!(client.get("/greet").await.text().await, "hello darpi #1");
+    assert_eq!(client.get("/greet").await.text().await, "hello darpi #2");
+    assert_eq!(client.get("/name").await.text().await, "darpi");
+}
+
+// every app has its own state types, even with the same container and state
+#[tokio::test]
+async fn apps_with_the_same_state() {
+    let first = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        state: {
+            Settings: Settings { name: "first".to_string() }
+        },
+        handlers: [{
+            route: "/name",
+            method: Method::GET,
+            handler: app_name
+        }]
+    });
+    let second = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        state: {
+            Settings: Settings { name: "second".to_string() }
+        },
+        handlers: [{
+            route: "/name",
+            method: Method::GET,
+            handler: app_name
+        }]
+    });
+    let first = TestClient::new(first.service());
+    let second = TestClient::new(second.service());
+
+    assert_eq!(first.get("/name").await.text().await, "first");
+    assert_eq!(second.get("/name").await.text().await, "second");
+}
+
+#[test]
+fn missing_state() {
+    trybuild::TestCases::new().compile_fail("tests/state/*.rs");
+}
+
+#[tokio::test]
+async fn state_without_container() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        state: {
+            Settings: Settings { name: "stateful".to_string() }
+        },
+        handlers: [{
+            route: "/name",
+            method: Method::GET,
+            handler: app_name
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.get("/name").await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.text().await, "stateful");
+}
+
+#[tokio::test]
+async fn state_outside_app() {
+    let mut req = RequestBuilder::get("/").build();
+    let requests = request_middleware::<count, _>(&mut req, Arc::new(()), ()).await;
+    assert_eq!(
+        requests.unwrap_err().to_string(),
+        format!(
+            "`{}` is not in the state of the app",
+            std::any::type_name::<Counter>()
+        )
+    );
+
+    State::default()
+        .with(Counter::default())
+        .insert_into(&mut req);
+    let requests = request_middleware::<count, _>(&mut req, Arc::new(()), ()).await;
+    assert_eq!(requests.unwrap(), 1);
+    let requests = request_middleware::<count, _>(&mut req, Arc::new(()), ()).await;
+    assert_eq!(requests.unwrap(), 2);
+}
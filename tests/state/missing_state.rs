@@ -0,0 +1,27 @@
+use darpi::{app, handler, Method};
+use std::sync::Arc;
+
+pub struct Settings {
+    name: String,
+}
+
+pub struct Counter;
+
+#[handler]
+async fn app_name(#[state] settings: Arc<Settings>) -> String {
+    settings.name.clone()
+}
+
+fn main() {
+    app!({
+        address: "127.0.0.1:0",
+        state: {
+            Counter: Counter
+        },
+        handlers: [{
+            route: "/name",
+            method: Method::GET,
+            handler: app_name
+        }]
+    });
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::get_raw;
+use darpi::listen::{receive_systemd_sockets, Address};
+use darpi::server::Bound;
+use darpi::{app, handler, Method};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use tokio::net::TcpStream;
+
+#[handler]
+async fn hello() -> &'static str {
+    "hello"
+}
+
+fn bind_systemd() -> Result<Bound, darpi::server::Error> {
+    app!({
+        address: "systemd",
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    })
+    .bind()
+}
+
+// the received sockets are global and receiving them changes the environment of the process,
+// so they are tested in order, in a test binary of their own
+#[test]
+fn systemd_sockets() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let err = rt.block_on(async { receive_systemd_sockets().unwrap_err() });
+    assert_eq!(
+        err.to_string(),
+        "the sockets of `LISTEN_FDS` have to be received before the runtime is started"
+    );
+    let err = rt.enter(|| bind_systemd().err().unwrap());
+    assert_eq!(
+        err.to_string(),
+        "could not listen on `systemd`: the sockets of `LISTEN_FDS` were not received, \
+         call `darpi::listen::receive_systemd_sockets` at the start of `main`"
+    );
+
+    std::env::set_var("LISTEN_PID", "1");
+    std::env::set_var("LISTEN_FDS", "1");
+    let err = receive_systemd_sockets().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the sockets of `LISTEN_FDS` were passed to another process"
+    );
+    assert!(std::env::var("LISTEN_FDS").is_err());
+    let err = rt.enter(|| bind_systemd().err().unwrap());
+    assert_eq!(
+        err.to_string(),
+        "could not listen on `systemd`: no sockets were passed with `LISTEN_FDS`"
+    );
+
+    // a passed file which isn't a socket is an error and stays open
+    let file = std::fs::File::open(file!()).unwrap();
+    std::env::set_var("LISTEN_PID", std::process::id().to_string());
+    std::env::set_var("LISTEN_FDS", "1");
+    std::env::set_var("LISTEN_FDS_FIRST_FD", file.as_raw_fd().to_string());
+    receive_systemd_sockets().unwrap();
+    let err = rt.enter(|| bind_systemd().err().unwrap());
+    assert!(err.to_string().contains("is not a socket"), "{}", err);
+    file.metadata().expect("the file was closed");
+
+    let passed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let passed_addr = passed.local_addr().unwrap();
+    std::env::set_var("LISTEN_PID", std::process::id().to_string());
+    std::env::set_var("LISTEN_FDS", "1");
+    std::env::set_var("LISTEN_FDS_FIRST_FD", passed.into_raw_fd().to_string());
+    receive_systemd_sockets().unwrap();
+
+    rt.block_on(async {
+        let server = bind_systemd().expect("could not bind");
+        assert_eq!(server.local_addrs(), &[Address::Tcp(passed_addr)]);
+        tokio::spawn(server);
+        assert!(
+            get_raw(TcpStream::connect(passed_addr).await.unwrap(), "/hello")
+                .await
+                .ends_with("hello")
+        );
+
+        // the sockets are listened on only once
+        let err = bind_systemd().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "could not listen on `systemd`: the sockets of `LISTEN_FDS` are already listened on"
+        );
+    });
+}
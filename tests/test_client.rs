@@ -0,0 +1,138 @@
+mod common;
+
+use common::Counter;
+use darpi::job::FutureJob;
+use darpi::once_cell::sync::Lazy;
+use darpi::response::ResponderError;
+use darpi::test::TestClient;
+use darpi::{
+    app, from_path, handler, header, job_factory, middleware, Body, Json, Method, RequestParts,
+    Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unauthorized")
+    }
+}
+
+impl ResponderError for Unauthorized {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+}
+
+#[middleware(Request)]
+async fn authorize(#[request_parts] rp: &RequestParts) -> Result<(), Unauthorized> {
+    match rp.headers.get("x-token") {
+        Some(_) => Ok(()),
+        None => Err(Unauthorized),
+    }
+}
+
+#[middleware(Response)]
+async fn served_by(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    r.headers_mut()
+        .insert("x-served-by", header::HeaderValue::from_static("darpi"));
+    Ok(())
+}
+
+static REQUESTS: Lazy<Counter> = Lazy::new(Counter::new);
+
+#[job_factory(Request)]
+async fn count() -> FutureJob {
+    async {
+        REQUESTS.add();
+    }
+    .into()
+}
+
+#[from_path]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserId {
+    id: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct User {
+    id: u64,
+    name: String,
+}
+
+#[handler]
+async fn hello() -> &'static str {
+    "hello"
+}
+
+#[handler({
+    middleware: {
+        request: [authorize]
+    }
+})]
+async fn update_user(#[path] p: UserId, #[body] name: Json<String>) -> Json<User> {
+    Json(User {
+        id: p.id,
+        name: name.into_inner(),
+    })
+}
+
+#[tokio::test]
+async fn test_client() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        jobs: {
+            request: [count]
+        },
+        middleware: {
+            response: [served_by]
+        },
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        },{
+            route: "/user/{id}",
+            method: Method::PUT,
+            handler: update_user
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    let resp = client.get("/hello").await;
+    resp.assert_status(StatusCode::OK)
+        .assert_header("x-served-by", "darpi");
+    assert_eq!(resp.text().await, "hello");
+
+    client
+        .get("/nope")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    client
+        .put_json("/user/1", &"petar")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let req = darpi::Request::put("/user/1")
+        .header("x-token", "secret")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#""petar""#))
+        .unwrap();
+    let resp = client.send(req).await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(
+        resp.json::<User>().await,
+        User {
+            id: 1,
+            name: "petar".to_string()
+        }
+    );
+
+    // request jobs run in the background
+    assert_eq!(REQUESTS.reached(4).await, 4);
+}
@@ -0,0 +1,189 @@
+use darpi::test::TestClient;
+use darpi::timeout::{TimedOut, Timeout};
+use darpi::{app, handler, header, middleware, Body, Method, Response, StatusCode};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+#[handler]
+async fn slow() -> &'static str {
+    tokio::time::delay_for(Duration::from_millis(300)).await;
+    FINISHED.store(true, Ordering::SeqCst);
+    "slow"
+}
+
+#[handler({
+    timeout: Duration::from_secs(5)
+})]
+async fn patient() -> &'static str {
+    tokio::time::delay_for(Duration::from_millis(300)).await;
+    "patient"
+}
+
+#[handler({
+    timeout: Timeout::new(Duration::from_millis(50)).status(StatusCode::GATEWAY_TIMEOUT)
+})]
+async fn impatient() -> &'static str {
+    tokio::time::delay_for(Duration::from_millis(300)).await;
+    "impatient"
+}
+
+#[handler]
+async fn fast() -> &'static str {
+    "fast"
+}
+
+#[middleware(Request)]
+async fn stall(#[handler] millis: u64) -> Result<(), Infallible> {
+    tokio::time::delay_for(Duration::from_millis(millis)).await;
+    Ok(())
+}
+
+#[middleware(Response)]
+async fn record_timeout(#[response] r: &mut Response<Body>) -> Result<(), Infallible> {
+    if let Some(timed_out) = r.extensions().get::<TimedOut>() {
+        let after = timed_out.after().as_millis().to_string();
+        r.headers_mut().insert(
+            "x-timed-out-after",
+            header::HeaderValue::from_str(&after).unwrap(),
+        );
+    }
+    Ok(())
+}
+
+#[middleware(Response)]
+async fn mark(
+    #[response] r: &mut Response<Body>,
+    #[handler] name: &'static str,
+) -> Result<(), Infallible> {
+    r.headers_mut()
+        .append("x-mark", header::HeaderValue::from_static(name));
+    Ok(())
+}
+
+#[middleware(Response)]
+async fn after_stall(
+    #[response] r: &mut Response<Body>,
+    #[handler] _stalled: (),
+) -> Result<(), Infallible> {
+    r.headers_mut()
+        .append("x-mark", header::HeaderValue::from_static("after_stall"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn timeouts() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        timeout: Duration::from_millis(100),
+        middleware: {
+            response: [record_timeout]
+        },
+        handlers: [{
+            route: "/slow",
+            method: Method::GET,
+            handler: slow
+        },{
+            route: "/patient",
+            method: Method::GET,
+            handler: patient
+        },{
+            route: "/impatient",
+            method: Method::GET,
+            handler: impatient
+        },{
+            route: "/fast",
+            method: Method::GET,
+            handler: fast
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    // the handler is cancelled and the response middleware sees the timeout
+    let resp = client.get("/slow").await;
+    resp.assert_status(StatusCode::SERVICE_UNAVAILABLE)
+        .assert_header("x-timed-out-after", "100");
+    assert_eq!(resp.text().await, "request timed out after 100ms");
+    tokio::time::delay_for(Duration::from_millis(400)).await;
+    assert!(!FINISHED.load(Ordering::SeqCst));
+
+    // the timeout of a handler replaces the one of the app
+    let resp = client.get("/patient").await;
+    resp.assert_status(StatusCode::OK);
+    assert_eq!(resp.text().await, "patient");
+
+    let resp = client.get("/impatient").await;
+    resp.assert_status(StatusCode::GATEWAY_TIMEOUT)
+        .assert_header("x-timed-out-after", "50");
+
+    client.get("/fast").await.assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn request_middleware_timeout() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        middleware: {
+            request: [stall(300)],
+            response: [record_timeout, after_stall(request(0))]
+        },
+        handlers: [{
+            route: "/fast",
+            method: Method::GET,
+            handler: fast
+        }]
+    })
+    .timeout(Timeout::new(Duration::from_millis(100)).status(StatusCode::GATEWAY_TIMEOUT));
+    let client = TestClient::new(app.service());
+
+    // the response middleware sees the timeout, unless it needs the value of the stalled middleware
+    let resp = client.get("/fast").await;
+    resp.assert_status(StatusCode::GATEWAY_TIMEOUT)
+        .assert_header("x-timed-out-after", "100");
+    assert!(resp.headers().get("x-mark").is_none());
+    assert_eq!(resp.text().await, "request timed out after 100ms");
+}
+
+#[tokio::test]
+async fn group_middleware_timeout() {
+    let app = app!({
+        address: "127.0.0.1:0",
+        timeout: Duration::from_millis(100),
+        middleware: {
+            response: [record_timeout, mark("app")]
+        },
+        handlers: [{
+            group: "/outer",
+            middleware: {
+                response: [mark("outer")]
+            },
+            handlers: [{
+                group: "/inner",
+                middleware: {
+                    request: [stall(300)],
+                    response: [mark("inner"), after_stall(request(0))]
+                },
+                handlers: [{
+                    route: "/fast",
+                    method: Method::GET,
+                    handler: fast
+                }]
+            }]
+        }]
+    });
+    let client = TestClient::new(app.service());
+
+    // the response middleware of the group and of everything around it runs, innermost first
+    let resp = client.get("/outer/inner/fast").await;
+    resp.assert_status(StatusCode::SERVICE_UNAVAILABLE)
+        .assert_header("x-timed-out-after", "100");
+    let marks: Vec<&str> = resp
+        .headers()
+        .get_all("x-mark")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(marks, ["inner", "outer", "app"]);
+}
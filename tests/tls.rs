@@ -0,0 +1,184 @@
+use darpi::tls::{CertificateProvider, PrivateKey};
+use darpi::{app, handler, hyper, Method, StatusCode};
+use hyper_rustls::HttpsConnector;
+use shaku::{module, Component, Interface};
+use std::path::Path;
+use std::time::Duration;
+use tokio_rustls::rustls::{Certificate, ClientConfig};
+
+fn make_container() -> Container {
+    Container::builder().build()
+}
+
+module! {
+    Container {
+        components = [NoCertificates],
+        providers = [],
+    }
+}
+
+pub trait Certificates: CertificateProvider + Interface {}
+
+/// A provider without certificates
+#[derive(Component)]
+#[shaku(interface = Certificates)]
+pub struct NoCertificates;
+
+impl Certificates for NoCertificates {}
+
+impl CertificateProvider for NoCertificates {
+    fn load(&self) -> std::io::Result<(Vec<Certificate>, PrivateKey)> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no certificates",
+        ))
+    }
+}
+
+#[handler]
+async fn hello() -> &'static str {
+    "hello over tls"
+}
+
+/// Writes a new self-signed certificate for `localhost` and returns it in DER
+fn write_cert(dir: &Path) -> Vec<u8> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+    cert.serialize_der().unwrap()
+}
+
+/// Makes a request with a client that only trusts `cert`
+async fn get(port: u16, cert: &[u8]) -> Result<(StatusCode, String), hyper::Error> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add(&Certificate(cert.to_vec()))
+        .expect("invalid certificate");
+
+    let mut http = hyper::client::HttpConnector::new();
+    http.enforce_http(false);
+    let client =
+        hyper::Client::builder().build::<_, hyper::Body>(HttpsConnector::from((http, config)));
+
+    let resp = client
+        .get(format!("https://localhost:{}/hello", port).parse().unwrap())
+        .await?;
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+}
+
+#[tokio::test]
+async fn tls() {
+    let dir = std::env::temp_dir().join(format!("darpi-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let first = write_cert(&dir);
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        tls: {
+            cert: cert_path,
+            key: key_path
+        },
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    });
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = app
+        .bind_with_shutdown(async {
+            let _ = rx.await;
+        })
+        .expect("could not bind");
+    let port = server.local_addr().port();
+    let server = tokio::spawn(server);
+
+    assert_eq!(
+        get(port, &first).await.unwrap(),
+        (StatusCode::OK, "hello over tls".to_string())
+    );
+
+    // the certificate is reloaded once the files change
+    tokio::time::delay_for(Duration::from_millis(1100)).await;
+    let second = write_cert(&dir);
+    tokio::time::delay_for(Duration::from_millis(1100)).await;
+
+    assert_eq!(
+        get(port, &second).await.unwrap(),
+        (StatusCode::OK, "hello over tls".to_string())
+    );
+    assert!(get(port, &first).await.is_err());
+
+    tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_certificate() {
+    let dir = std::env::temp_dir().join(format!("darpi-tls-invalid-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, "not a certificate").unwrap();
+    std::fs::write(&key_path, "not a key").unwrap();
+
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        tls: {
+            cert: cert_path.clone(),
+            key: key_path
+        },
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    });
+
+    // reported by `bind`, like the other errors of starting the server
+    let err = app.bind().err().expect("invalid certificates were loaded");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "could not load the tls certificates: no certificates in {}",
+            cert_path.display()
+        )
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let app = app!({
+        address: "127.0.0.1:0",
+        container: {
+            factory: make_container(),
+            type: Container
+        },
+        tls: {
+            provider: dyn Certificates
+        },
+        handlers: [{
+            route: "/hello",
+            method: Method::GET,
+            handler: hello
+        }]
+    });
+    let err = app.bind().err().expect("missing certificates were loaded");
+    assert_eq!(
+        err.to_string(),
+        "could not load the tls certificates: no certificates"
+    );
+}